@@ -125,7 +125,7 @@ impl ToSocketAddrs for String {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-impl<'a> ToSocketAddrs for &'a [SocketAddr] {
+impl ToSocketAddrs for &[SocketAddr] {
     type Iter = vec::IntoIter<SocketAddr>;
 
     fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
@@ -1,27 +1,73 @@
 mod context;
 mod net;
+mod report;
 mod runtime;
 mod time;
 
 pub mod node;
 pub mod spawn;
+pub mod task;
 
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::hash::RandomState;
+use std::cell::Cell;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::net::IpAddr;
+use std::ops::Bound;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 
+use context::ContextGuard;
+use futures::task::ArcWake;
 use net::Network;
 use node::Node;
 use node::NodeHandle;
+use time::Clock;
 
+pub use net::Faults;
+pub use net::GilbertElliott;
+pub use net::Latency;
+pub use net::LinkConfig;
+pub use net::Loss;
+pub use net::NetworkChange;
+pub use net::NetworkConfig;
 pub use net::NetworkHandle;
+pub use net::NetworkSchedule;
+pub use net::NetworkStats;
+pub use net::PacketAction;
+pub use net::PacketMatcher;
+pub use net::RuleHandle;
 pub use net::UdpSocket;
+pub use report::NodeReport;
+pub use report::StuckReport;
+pub use runtime::AbortHandle;
+pub use runtime::JoinError;
+pub use runtime::JoinHandle;
+pub use runtime::PanicContext;
+pub use runtime::PanicPolicy;
+pub use runtime::SchedulingPolicy;
+pub use runtime::TaskId;
+pub use runtime::TaskInfo;
+pub use runtime::TaskState;
 pub use spawn::spawn;
+pub use time::interval;
+pub use time::interval_at;
 pub use time::now;
 pub use time::sleep;
+pub use time::sleep_until;
+pub use time::system_time;
+pub use time::timeout;
+pub use time::Elapsed;
+pub use time::Interval;
+pub use time::MissedTickBehavior;
+pub use time::Sleep;
+pub use time::Timeout;
 
 use crate::net::ip_addr::ToIpAddr;
+use crate::time::Timestamp;
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -31,16 +77,82 @@ pub fn in_sim() -> bool {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Result of running the simulation with [`Sim::run_until`] and friends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// There are no runnable tasks, timers and network events left
+    /// before the deadline, the global time is advanced to the deadline.
+    Quiescent,
+    /// Deadline has been reached, the global time is equal to the deadline.
+    DeadlineReached,
+    /// Condition returned `true`.
+    ConditionMet,
+    /// Number of steps set with [`Sim::set_step_limit`] has been reached.
+    StepLimitReached,
+    /// There are no runnable tasks, timers and network events,
+    /// but some tasks are not finished.
+    Deadlock(StuckReport),
+    /// Steps are made, but the global time does not advance
+    /// for the number of steps set with [`Sim::set_livelock_threshold`].
+    Livelock(StuckReport),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Wakes the future passed to [`Sim::block_on`].
+struct Waker(AtomicBool);
+
+impl Default for Waker {
+    fn default() -> Self {
+        // future must be polled first time
+        Self(AtomicBool::new(true))
+    }
+}
+
+impl ArcWake for Waker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub struct Sim {
-    nodes: HashMap<IpAddr, Node>,
+    nodes: BTreeMap<IpAddr, Node>,
     network: Network,
+    clock: Clock,
+    last_stepped: Cell<Option<IpAddr>>,
+    step_limit: Option<usize>,
+    livelock_threshold: usize,
+    seed: u64,
+    scheduling_policy: SchedulingPolicy,
+    panic_policy: PanicPolicy,
+    // runs the future passed to `block_on`
+    controller: Node,
 }
 
 impl Sim {
+    const DEFAULT_LIVELOCK_THRESHOLD: usize = 1_000_000;
+
     pub fn new(seed: u64) -> Self {
+        Self::with_network_config(seed, NetworkConfig::default())
+    }
+
+    pub fn with_network_config(seed: u64, config: NetworkConfig) -> Self {
+        let clock = Clock::new();
+        let network = Network::new(seed, clock.clone(), config);
+        let controller = Node::controller(network.handle(), clock.clone(), seed);
         Self {
-            nodes: HashMap::with_hasher(RandomState::new()),
-            network: Network::new(seed),
+            nodes: BTreeMap::new(),
+            network,
+            clock,
+            last_stepped: Cell::new(None),
+            step_limit: None,
+            livelock_threshold: Self::DEFAULT_LIVELOCK_THRESHOLD,
+            seed,
+            scheduling_policy: Default::default(),
+            panic_policy: Default::default(),
+            controller,
         }
     }
 
@@ -54,23 +166,290 @@ impl Sim {
         self.network.handle()
     }
 
+    pub fn time(&self) -> Timestamp {
+        self.clock.time()
+    }
+
+    /// Sets the wall-clock time which corresponds
+    /// to the start of the simulation, `UNIX_EPOCH` by default.
+    pub fn set_epoch(&mut self, epoch: SystemTime) {
+        self.clock.set_epoch(epoch);
+    }
+
+    /// Sets the scheduling policy for the nodes built after the call,
+    /// unless it is set with [`NodeBuilder::scheduling_policy`](node::NodeBuilder::scheduling_policy).
+    pub fn set_scheduling_policy(&mut self, policy: SchedulingPolicy) {
+        self.scheduling_policy = policy;
+    }
+
+    /// Sets the panic policy for the nodes built after the call,
+    /// unless it is set with [`NodeBuilder::panic_policy`](node::NodeBuilder::panic_policy).
+    /// Also applies to the tasks spawned in [`Sim::block_on`].
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+        self.controller.handle().set_panic_policy(policy);
+    }
+
+    pub fn crash(&self, addr: impl ToIpAddr) {
+        self.expect_node(addr).crash();
+    }
+
+    pub fn restart(&self, addr: impl ToIpAddr) {
+        self.expect_node(addr).restart();
+    }
+
+    /// Makes one step of the simulation.
+    ///
+    /// Runnable tasks are polled first, one task per step, round-robin over nodes.
+    /// If there are no runnable tasks, the global time is advanced to the earliest
+    /// timer or network event among all nodes, and all events scheduled
+    /// at that time are fired.
+    ///
+    /// Returns `false` if there are no runnable tasks and no pending events.
+    pub fn next_step(&self) -> bool {
+        if let Some(node) = self.next_runnable_node() {
+            self.last_stepped.set(Some(node.ip()));
+            node.poll_task();
+            true
+        } else if let Some(time) = self.next_event_timestamp() {
+            self.advance_to_time(time);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn make_steps(&self) -> usize {
-        let mut was_step = true;
         let mut steps = 0;
-        let mut nodes = self.nodes.keys().cloned().collect::<Vec<_>>();
-        nodes.sort();
-        while was_step {
-            was_step = false;
-            for node in nodes.iter() {
-                let node = self.nodes.get(node).unwrap().handle();
-                let node_steps = node.make_steps(None);
-                if node_steps > 0 {
-                    steps += node_steps;
-                    was_step = true;
+        while self.next_step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Limits the number of steps made by a single call of [`Sim::run_until`],
+    /// [`Sim::run_for`], [`Sim::run_until_cond`] or [`Sim::block_on`].
+    pub fn set_step_limit(&mut self, limit: usize) {
+        self.step_limit = Some(limit);
+    }
+
+    /// Sets the number of steps without advancing the global time
+    /// after which [`Sim::run_until`] and friends report a livelock
+    /// and [`Sim::block_on`] panics.
+    pub fn set_livelock_threshold(&mut self, steps: usize) {
+        self.livelock_threshold = steps;
+    }
+
+    /// Runs the future outside of the nodes until it completes,
+    /// stepping the simulation while the future is pending.
+    ///
+    /// The future runs in the simulated time: it can use [`sleep`], [`timeout`],
+    /// [`spawn`] and other time utilities, await node join handles and crash,
+    /// restart or pause nodes. Sockets can not be used in it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the future is pending while the simulation has nothing to do,
+    /// or if the step limit or the livelock threshold is reached. Each poll
+    /// of the future or of a task spawned by it counts as a step.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let controller = self.controller.handle();
+        let woken = Arc::new(Waker::default());
+        let waker = futures::task::waker(woken.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        let mut steps = 0;
+        let mut stuck_steps = 0;
+        loop {
+            if self.step_limit.is_some_and(|limit| steps >= limit) {
+                panic!("block_on future can not complete, step limit is reached");
+            }
+            if stuck_steps >= self.livelock_threshold {
+                panic!(
+                    "block_on future can not complete, livelock detected, {}",
+                    self.stuck_report()
+                );
+            }
+            let time_before = self.time();
+            steps += 1;
+            if woken.0.swap(false, Ordering::Relaxed) {
+                let _guard = ContextGuard::new(controller.clone());
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            } else if !controller.poll_task() && !self.next_step() {
+                match self.detect_deadlock() {
+                    Some(report) => panic!("block_on future can not complete, {}", report),
+                    None => panic!("block_on future can not complete, simulation is quiescent"),
                 }
             }
+            if self.time() == time_before {
+                stuck_steps += 1;
+            } else {
+                stuck_steps = 0;
+            }
+        }
+    }
+
+    /// Snapshot of the unfinished tasks of every node.
+    pub fn tasks(&self) -> Vec<NodeReport> {
+        self.nodes
+            .values()
+            .map(|node| node.handle().report())
+            .collect()
+    }
+
+    /// Returns report if there are no runnable tasks, timers and network events,
+    /// but some tasks are not finished. Tasks of paused nodes are not considered.
+    pub fn detect_deadlock(&self) -> Option<StuckReport> {
+        if self.next_event_timestamp().is_some() {
+            return None;
+        }
+        let report = self.stuck_report();
+        if report.nodes.is_empty() {
+            None
+        } else {
+            Some(report)
+        }
+    }
+
+    pub fn run_for(&self, duration: Duration) -> RunOutcome {
+        self.run_until(self.time() + duration)
+    }
+
+    pub fn run_until(&self, deadline: Timestamp) -> RunOutcome {
+        self.run_until_cond(|_| false, deadline)
+    }
+
+    /// Runs the simulation until `cond` returns `true` or the global time
+    /// reaches `deadline`. Condition is checked before every step.
+    pub fn run_until_cond(
+        &self,
+        mut cond: impl FnMut(&Sim) -> bool,
+        deadline: Timestamp,
+    ) -> RunOutcome {
+        let mut steps = 0;
+        let mut stuck_steps = 0;
+        loop {
+            if cond(self) {
+                return RunOutcome::ConditionMet;
+            }
+            let Some(time) = self.next_event_timestamp() else {
+                let deadlock = self.detect_deadlock();
+                // paused nodes must see the time passing
+                if self.time() < deadline {
+                    self.advance_to_time(deadline);
+                }
+                return match deadlock {
+                    Some(report) => RunOutcome::Deadlock(report),
+                    None => RunOutcome::Quiescent,
+                };
+            };
+            if time > deadline {
+                if self.time() < deadline {
+                    self.advance_to_time(deadline);
+                }
+                return RunOutcome::DeadlineReached;
+            }
+            if self.step_limit.is_some_and(|limit| steps >= limit) {
+                return RunOutcome::StepLimitReached;
+            }
+            if stuck_steps >= self.livelock_threshold {
+                return RunOutcome::Livelock(self.stuck_report());
+            }
+            let time_before = self.time();
+            self.next_step();
+            steps += 1;
+            if self.time() == time_before {
+                stuck_steps += 1;
+            } else {
+                stuck_steps = 0;
+            }
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    pub(crate) fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    pub(crate) fn scheduling_policy(&self) -> SchedulingPolicy {
+        self.scheduling_policy
+    }
+
+    pub(crate) fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Seed for the random generators of the node,
+    /// so nodes do not affect random sequences of each other.
+    pub(crate) fn node_seed(&self, ip: IpAddr) -> u64 {
+        let ip = match ip {
+            IpAddr::V4(ip) => u32::from(ip) as u64,
+            IpAddr::V6(ip) => {
+                let ip = u128::from(ip);
+                (ip >> 64) as u64 ^ ip as u64
+            }
+        };
+        self.seed ^ ip.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn expect_node(&self, addr: impl ToIpAddr) -> NodeHandle {
+        let addr = addr.to_ip_addr().unwrap();
+        self.node(addr)
+            .unwrap_or_else(|| panic!("node '{}' is not registered", addr))
+    }
+
+    fn stuck_report(&self) -> StuckReport {
+        let nodes = self
+            .nodes
+            .values()
+            .map(|node| node.handle())
+            .filter(|node| !node.is_paused())
+            .map(|node| node.report())
+            .filter(|node| !node.tasks.is_empty())
+            .collect();
+        StuckReport {
+            time: self.time(),
+            nodes,
+        }
+    }
+
+    fn next_runnable_node(&self) -> Option<NodeHandle> {
+        let after = match self.last_stepped.get() {
+            Some(ip) => self.nodes.range((Bound::Excluded(ip), Bound::Unbounded)),
+            None => self.nodes.range(..),
+        };
+        after
+            .chain(self.nodes.iter())
+            .map(|(_, node)| node.handle())
+            .find(|node| node.has_work())
+    }
+
+    fn next_event_timestamp(&self) -> Option<Timestamp> {
+        if self.nodes.values().any(|node| node.handle().has_work()) {
+            return Some(self.time());
+        }
+        self.nodes
+            .values()
+            .chain([&self.controller])
+            .filter_map(|node| node.handle().next_timer_timestamp())
+            .chain(self.network.handle().next_event_timestamp())
+            .min()
+            .map(|time| time.max(self.time()))
+    }
+
+    fn advance_to_time(&self, time: Timestamp) {
+        self.clock.advance_to_time(time);
+        self.network.handle().advance_to_time(time);
+        for node in self.nodes.values().chain([&self.controller]) {
+            node.handle().fire_timers(time);
         }
-        steps
     }
 
     ////////////////////////////////////////////////////////////////////////////////
@@ -87,3 +466,313 @@ impl Sim {
         }
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell, future::poll_fn, net::SocketAddr, rc::Rc, task::Poll, time::Duration,
+    };
+
+    use super::{
+        node::NodeBuilder, now, sleep, spawn, timeout, PanicPolicy, RunOutcome, Sim, UdpSocket,
+    };
+
+    #[test]
+    fn global_time() {
+        let mut sim = Sim::new(123);
+        let node1 = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let node2 = NodeBuilder::with_ip("10.0.0.2")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for (node, secs) in [(&node1, 2), (&node2, 1)] {
+            node.spawn({
+                let log = log.clone();
+                let ip = node.ip();
+                async move {
+                    sleep(Duration::from_secs(secs)).await;
+                    log.borrow_mut().push((ip, now()));
+                }
+            });
+        }
+        sim.make_steps();
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                (node2.ip(), Duration::from_secs(1)),
+                (node1.ip(), Duration::from_secs(2))
+            ]
+        );
+        assert_eq!(sim.time(), Duration::from_secs(2));
+        assert_eq!(node1.time(), sim.time());
+        assert_eq!(node2.time(), sim.time());
+    }
+
+    #[test]
+    fn causality() {
+        let mut sim = Sim::new(123);
+        let sender = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let receiver = NodeBuilder::with_ip("10.0.0.2")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let send_time = Rc::new(RefCell::new(None));
+        let recv_time = Rc::new(RefCell::new(None));
+        receiver.spawn(async {
+            // receiver has a far timer, but must not run ahead of the sender
+            sleep(Duration::from_secs(100)).await;
+        });
+        receiver.spawn({
+            let recv_time = recv_time.clone();
+            async move {
+                let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+                let mut buf = [0u8; 5];
+                socket.recv_from(&mut buf).await;
+                *recv_time.borrow_mut() = Some(now());
+            }
+        });
+        sender.spawn({
+            let send_time = send_time.clone();
+            let to = SocketAddr::new(receiver.ip(), 80);
+            async move {
+                let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+                sleep(Duration::from_secs(1)).await;
+                *send_time.borrow_mut() = Some(now());
+                socket.send_to(b"hello", to).unwrap();
+            }
+        });
+        sim.make_steps();
+        let send_time = send_time.borrow().unwrap();
+        let recv_time = recv_time.borrow().unwrap();
+        assert_eq!(send_time, Duration::from_secs(1));
+        assert!(recv_time > send_time);
+        assert!(recv_time < Duration::from_secs(100));
+        assert_eq!(sim.time(), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn run_for_periodic() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let ticks = Rc::new(RefCell::new(0));
+        node.spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    sleep(Duration::from_secs(1)).await;
+                    *ticks.borrow_mut() += 1;
+                }
+            }
+        });
+        let outcome = sim.run_for(Duration::from_millis(10500));
+        assert_eq!(outcome, RunOutcome::DeadlineReached);
+        assert_eq!(sim.time(), Duration::from_millis(10500));
+        assert_eq!(*ticks.borrow(), 10);
+
+        let outcome = sim.run_until_cond(|_| *ticks.borrow() == 15, Duration::from_secs(100));
+        assert_eq!(outcome, RunOutcome::ConditionMet);
+        assert_eq!(sim.time(), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn run_until_quiescent() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        node.spawn(async {
+            sleep(Duration::from_secs(1)).await;
+        });
+        let outcome = sim.run_until(Duration::from_secs(5));
+        assert_eq!(outcome, RunOutcome::Quiescent);
+        assert_eq!(sim.time(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        sim.set_step_limit(100);
+        node.spawn(poll_fn(|cx| {
+            cx.waker().wake_by_ref();
+            Poll::<()>::Pending
+        }));
+        let outcome = sim.run_for(Duration::from_secs(1));
+        assert_eq!(outcome, RunOutcome::StepLimitReached);
+        assert_eq!(sim.time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn deadlock() {
+        let mut sim = Sim::new(123);
+        for ip in ["10.0.0.1", "10.0.0.2"] {
+            let node = NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
+            node.spawn(async {
+                // both nodes wait for each other
+                let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+                let mut buf = [0u8; 1];
+                socket.recv_from(&mut buf).await;
+            });
+            node.spawn(async {});
+        }
+        sim.make_steps();
+        let report = sim.detect_deadlock().unwrap();
+        let RunOutcome::Deadlock(outcome_report) = sim.run_for(Duration::from_secs(1)) else {
+            panic!("deadlock is not detected")
+        };
+        assert_eq!(outcome_report.nodes, report.nodes);
+        assert_eq!(report.nodes.len(), 2);
+        for node in report.nodes.iter() {
+            assert_eq!(node.tasks.len(), 1);
+            assert_eq!(node.tasks[0].location.file(), file!());
+        }
+        let display = report.to_string();
+        assert!(display.contains("node 10.0.0.1 has 1 unfinished task(s)"));
+        assert!(display.contains(file!()));
+
+        sim.node("10.0.0.2").unwrap().pause();
+        assert_eq!(sim.detect_deadlock().unwrap().nodes.len(), 1);
+    }
+
+    #[test]
+    fn livelock() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        sim.set_livelock_threshold(1000);
+        node.spawn(async {
+            sleep(Duration::from_secs(1)).await;
+            poll_fn(|cx| {
+                cx.waker().wake_by_ref();
+                Poll::<()>::Pending
+            })
+            .await;
+        });
+        let RunOutcome::Livelock(report) = sim.run_for(Duration::from_secs(10)) else {
+            panic!("livelock is not detected")
+        };
+        assert_eq!(report.time, Duration::from_secs(1));
+        assert_eq!(report.nodes[0].tasks.len(), 1);
+        assert!(sim.detect_deadlock().is_none());
+    }
+
+    #[test]
+    fn block_on() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let log = RefCell::new(Vec::new());
+        let result = sim.block_on(async {
+            let handle = node.spawn(async {
+                sleep(Duration::from_secs(2)).await;
+                now()
+            });
+            log.borrow_mut().push(now());
+            sleep(Duration::from_secs(1)).await;
+            log.borrow_mut().push(now());
+            let finished = handle.await.unwrap();
+            log.borrow_mut().push(now());
+            let background = spawn(async {
+                sleep(Duration::from_secs(1)).await;
+                now()
+            });
+            assert!(timeout(Duration::from_millis(100), background)
+                .await
+                .is_err());
+            finished
+        });
+        assert_eq!(result, Duration::from_secs(2));
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                Duration::ZERO,
+                Duration::from_secs(1),
+                Duration::from_secs(2)
+            ]
+        );
+        assert_eq!(sim.time(), Duration::from_millis(2100));
+    }
+
+    #[test]
+    #[should_panic(expected = "block_on future can not complete")]
+    fn block_on_deadlock() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let (_sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        sim.block_on(async {
+            node.spawn(receiver).await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "block_on future can not complete, step limit is reached")]
+    fn block_on_step_limit() {
+        let mut sim = Sim::new(123);
+        sim.set_step_limit(100);
+        sim.block_on(async {
+            loop {
+                sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "livelock detected, simulation got stuck at 0ns")]
+    fn block_on_livelock() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        sim.set_livelock_threshold(1000);
+        sim.block_on(async {
+            node.spawn(poll_fn(|cx| {
+                cx.waker().wake_by_ref();
+                Poll::<()>::Pending
+            }))
+            .await
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn block_on_panic_captured() {
+        let mut sim = Sim::new(123);
+        sim.set_panic_policy(PanicPolicy::Capture);
+        let result = sim.block_on(async { spawn(async { panic!("boom") }).await });
+        assert!(result.unwrap_err().is_panic());
+    }
+
+    #[test]
+    #[should_panic(expected = "panicked in block_on at 1s (seed 123): boom")]
+    fn block_on_panic_propagated() {
+        let sim = Sim::new(123);
+        sim.block_on(async {
+            sleep(Duration::from_secs(1)).await;
+            spawn(async { panic!("boom") }).await.unwrap();
+        });
+    }
+}
@@ -2,17 +2,21 @@ use super::node::NodeHandle;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct ContextGuard {}
+/// Sets the current node and restores the previous one on drop.
+pub struct ContextGuard {
+    prev: Option<NodeHandle>,
+}
 
 impl ContextGuard {
     pub fn new(handle: NodeHandle) -> Self {
+        let prev = NodeHandle::get_current();
         NodeHandle::set(Some(handle));
-        Self {}
+        Self { prev }
     }
 }
 
 impl Drop for ContextGuard {
     fn drop(&mut self) {
-        NodeHandle::set(None)
+        NodeHandle::set(self.prev.take())
     }
 }
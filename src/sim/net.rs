@@ -1,34 +1,41 @@
 use std::{
     cell::RefCell,
-    collections::{hash_map::Entry, BinaryHeap},
+    collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet},
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     rc::{Rc, Weak},
     time::Duration,
 };
 
 use datagram::Datagram;
-use event::NetworkEvent;
+use event::{EventKind, NetworkEvent};
+use intercept::Rule;
 use registry::{SocketData, SocketRegistry};
 
+mod config;
 mod datagram;
 mod event;
+mod intercept;
 mod registry;
+mod schedule;
+mod stats;
 mod topology;
 mod udp;
 
-use rand::{
-    distributions::uniform::{UniformDuration, UniformSampler},
-    rngs::StdRng,
-    Rng, SeedableRng,
-};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use topology::NetworkTopology;
 use udp::UpdSocketData;
 
 use crate::{net::ip_addr::ToIpAddr, time::Timestamp};
 
-use super::now;
+use super::time::Clock;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
 
+pub use config::{Faults, GilbertElliott, Latency, LinkConfig, Loss, NetworkConfig};
+pub use intercept::{PacketAction, PacketMatcher, RuleHandle};
+pub use schedule::{NetworkChange, NetworkSchedule};
+pub use stats::NetworkStats;
 pub use udp::UdpSocket;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -36,24 +43,154 @@ pub use udp::UdpSocket;
 struct NetworkState {
     registry: SocketRegistry,
     rng: StdRng,
-    min_delay: Duration,
-    max_delay: Duration,
-    drop_rate: f64,
+    config: NetworkConfig,
     events: BinaryHeap<NetworkEvent>,
+    next_event_seq: u64,
     topology: NetworkTopology,
+    clock: Clock,
+    stats: NetworkStats,
+    // links in the bad state of the bursty loss model
+    bad_links: HashSet<(IpAddr, IpAddr)>,
+    // time when links finish transmitting the queued packets
+    busy_links: HashMap<(IpAddr, IpAddr), Timestamp>,
+    // interception rules in the order of adding
+    rules: Vec<Rc<Rule>>,
+    next_rule_id: u64,
+}
+
+/// Reason of a packet drop on the route.
+enum Dropped {
+    Lost,
+    Overflow,
 }
 
 impl NetworkState {
-    pub fn new(seed: u64) -> Self {
+    pub fn new(seed: u64, clock: Clock, config: NetworkConfig) -> Self {
         Self {
             registry: Default::default(),
             rng: StdRng::seed_from_u64(seed),
-            min_delay: Network::DEFAULT_MIN_DELAY,
-            max_delay: Network::DEFAULT_MAX_DELAY,
-            drop_rate: Network::DEFAULT_DROP_RATE,
+            config,
             events: Default::default(),
+            next_event_seq: 0,
             topology: NetworkTopology::new(),
+            clock,
+            stats: Default::default(),
+            bad_links: Default::default(),
+            busy_links: Default::default(),
+            rules: Default::default(),
+            next_rule_id: 0,
+        }
+    }
+
+    fn is_lost(&mut self, from: IpAddr, to: IpAddr, loss: Loss) -> bool {
+        let mut bad = self.bad_links.contains(&(from, to));
+        let lost = loss.sample(&mut bad, &mut self.rng);
+        if bad {
+            self.bad_links.insert((from, to));
+        } else {
+            self.bad_links.remove(&(from, to));
         }
+        lost
+    }
+
+    /// Returns the delay of the packet queued on the link at `arrival`,
+    /// or `None` if the queue is full.
+    fn transmit(
+        &mut self,
+        link: (IpAddr, IpAddr),
+        config: &NetworkConfig,
+        arrival: Timestamp,
+        size: usize,
+    ) -> Option<Duration> {
+        let Some(bandwidth) = config.bandwidth else {
+            return Some(Duration::ZERO);
+        };
+        let start = match self.busy_links.get(&link) {
+            Some(busy_until) => arrival.max(*busy_until),
+            None => arrival,
+        };
+        let wait = start - arrival;
+        if let Some(capacity) = config.queue_capacity {
+            // bytes not transmitted yet
+            let queued = wait.as_nanos() * bandwidth as u128 / NANOS_PER_SEC;
+            if queued + size as u128 > capacity as u128 {
+                return None;
+            }
+        }
+        let transmission =
+            Duration::from_nanos((size as u128 * NANOS_PER_SEC / bandwidth as u128) as u64);
+        self.busy_links.insert(link, start + transmission);
+        Some(wait + transmission)
+    }
+
+    /// Computes the delay of the packet along the route.
+    /// Queues are updated at the time of sending.
+    fn route_delay(
+        &mut self,
+        route: &[(IpAddr, IpAddr)],
+        size: usize,
+    ) -> Result<Duration, Dropped> {
+        // delay and losses accumulate on every hop
+        let now = self.clock.time();
+        let mut delay = Duration::ZERO;
+        for (from, to) in route.iter() {
+            let config = self.topology.link_config(*from, *to).resolve(&self.config);
+            delay += self
+                .transmit((*from, *to), &config, now + delay, size)
+                .ok_or(Dropped::Overflow)?;
+            if self.is_lost(*from, *to, config.loss) {
+                return Err(Dropped::Lost);
+            }
+            delay += config.latency.sample(&mut self.rng);
+            if !config.jitter.is_zero() {
+                delay += self.rng.gen_range(Duration::ZERO..=config.jitter);
+            }
+        }
+        Ok(delay)
+    }
+
+    /// Applies reordering and corruption faults and schedules the delivery.
+    fn schedule(
+        &mut self,
+        sender: SocketAddr,
+        receiver: SocketAddr,
+        mut data: Vec<u8>,
+        delay: Duration,
+    ) {
+        let faults = self.config.faults;
+        let mut delay = delay;
+        if faults.reordering > 0.0 && self.rng.gen_bool(faults.reordering) {
+            delay += self.rng.gen_range(Duration::ZERO..=faults.reorder_window);
+            self.stats.reordered += 1;
+        }
+        if faults.bit_flip > 0.0 && !data.is_empty() && self.rng.gen_bool(faults.bit_flip) {
+            let bit = self.rng.gen_range(0..data.len() * 8);
+            data[bit / 8] ^= 1 << (bit % 8);
+            self.stats.bit_flipped += 1;
+        }
+        if faults.truncation > 0.0 && !data.is_empty() && self.rng.gen_bool(faults.truncation) {
+            let len = self.rng.gen_range(0..data.len());
+            data.truncate(len);
+            self.stats.truncated += 1;
+        }
+        self.push_event(
+            self.clock.time().saturating_add(delay),
+            EventKind::Datagram {
+                sender,
+                receiver,
+                data,
+            },
+        );
+    }
+
+    fn push_event(&mut self, timestamp: Timestamp, kind: EventKind) {
+        let seq = self.next_event_seq;
+        self.next_event_seq += 1;
+        self.events.push(NetworkEvent {
+            timestamp,
+            seq,
+            kind,
+        });
     }
 }
 
@@ -62,12 +199,11 @@ impl NetworkState {
 pub(crate) struct Network(Rc<RefCell<NetworkState>>);
 
 impl Network {
-    const DEFAULT_MIN_DELAY: Duration = Duration::from_millis(100);
-    const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(500);
-    const DEFAULT_DROP_RATE: f64 = 0.05;
-
-    pub(crate) fn new(seed: u64) -> Self {
-        Self(Rc::new(RefCell::new(NetworkState::new(seed))))
+    pub(crate) fn new(seed: u64, clock: Clock, config: NetworkConfig) -> Self {
+        config.validate();
+        Self(Rc::new(RefCell::new(NetworkState::new(
+            seed, clock, config,
+        ))))
     }
 
     pub fn handle(&self) -> NetworkHandle {
@@ -100,53 +236,91 @@ impl NetworkHandle {
         self.state().borrow_mut().registry.0.remove(&addr).unwrap();
     }
 
+    pub(crate) fn deregister_node_sockets(&self, ip: IpAddr) {
+        self.state()
+            .borrow_mut()
+            .registry
+            .0
+            .retain(|addr, _| addr.ip() != ip);
+    }
+
     fn send_upd_packet(&self, from: SocketAddr, to: SocketAddr, packet: &[u8]) -> bool {
         let state = self.state();
-        let mut state = state.borrow_mut();
-        // 'from' socket must be registered
-        let SocketData::Udp(from_socket) =
-            state.registry.0.get(&from).expect("'from' not registered")
-        else {
-            panic!("socket has inconsistent type")
-        };
-        // 'from' socket is not alive
-        let Some(from_socket) = from_socket.upgrade() else {
-            return true;
+        let (sender, receiver, rules) = {
+            let mut state = state.borrow_mut();
+            // 'from' socket must be registered
+            let SocketData::Udp(from_socket) =
+                state.registry.0.get(&from).expect("'from' not registered")
+            else {
+                panic!("socket has inconsistent type")
+            };
+            // 'from' socket is not alive
+            let Some(from_socket) = from_socket.upgrade() else {
+                return true;
+            };
+            let Some(SocketData::Udp(to_socket)) = state.registry.0.get(&to) else {
+                return true;
+            };
+            // 'to' socket is not alive
+            let Some(to_socket) = to_socket.upgrade() else {
+                return true;
+            };
+            let sender = from_socket.borrow().local_addr;
+            let receiver = to_socket.borrow().local_addr;
+            state.stats.sent += 1;
+            (sender, receiver, state.rules.clone())
         };
-        let Some(SocketData::Udp(to_socket)) = state.registry.0.get(&to) else {
+        // rules run user closures, so the state is not borrowed
+        let mut data = packet.to_vec();
+        let interception = intercept::intercept(&rules, sender, receiver, &mut data);
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        if interception.matched {
+            state.stats.intercepted += 1;
+        }
+        if interception.dropped {
             return true;
-        };
-        // 'to' socket is not alive
-        let Some(to_socket) = to_socket.upgrade() else {
+        }
+        // drop if not connected
+        let Some(route) = state.topology.route(sender.ip(), receiver.ip()) else {
+            state.stats.unreachable += 1;
             return true;
         };
         // package dropped
-        let rand_num = state.rng.gen_range(0.0..1.0);
-        if to_socket.borrow().local_addr != from_socket.borrow().local_addr
-            && rand_num < state.drop_rate
+        if route.is_empty()
+            && receiver != sender
+            && state.is_lost(sender.ip(), receiver.ip(), state.config.loss)
         {
+            state.stats.lost += 1;
             return true;
         }
-        // drop if not connected
-        let Some(hops) = state.topology.hops(
-            from_socket.borrow().local_addr.ip(),
-            to_socket.borrow().local_addr.ip(),
-        ) else {
-            return true;
-        };
-        // package not dropped
-        let delay = UniformDuration::new(state.min_delay, state.max_delay)
-            .sample(&mut state.rng)
-            .checked_mul(hops as u32)
-            .unwrap();
-        let timestamp = now() + delay;
-        let event = NetworkEvent {
-            timestamp,
-            sender: from_socket.borrow().local_addr,
-            receiver: to_socket.borrow().local_addr,
-            data: Vec::from_iter(packet.iter().cloned()),
+        let delay = match state.route_delay(&route, data.len()) {
+            Ok(delay) => delay,
+            Err(Dropped::Lost) => {
+                state.stats.lost += 1;
+                return true;
+            }
+            Err(Dropped::Overflow) => {
+                state.stats.overflowed += 1;
+                return true;
+            }
         };
-        state.events.push(event);
+        state.schedule(sender, receiver, data.clone(), delay + interception.delay);
+        let mut copies = interception.copies;
+        let duplication = state.config.faults.duplication;
+        if duplication > 0.0 && state.rng.gen_bool(duplication) {
+            copies += 1;
+        }
+        for _ in 0..copies {
+            match state.route_delay(&route, data.len()) {
+                Ok(delay) => {
+                    state.schedule(sender, receiver, data.clone(), delay + interception.delay);
+                    state.stats.duplicated += 1;
+                }
+                Err(Dropped::Lost) => state.stats.lost += 1,
+                Err(Dropped::Overflow) => state.stats.overflowed += 1,
+            }
+        }
         false
     }
 
@@ -164,10 +338,227 @@ impl NetworkHandle {
         self.state().borrow_mut().topology.repair(group);
     }
 
+    /// Drops packets sent from `from` to `to` whatever the route is,
+    /// the opposite direction is not affected.
+    pub fn block(&self, from: impl ToIpAddr, to: impl ToIpAddr) {
+        self.state()
+            .borrow_mut()
+            .topology
+            .block(from.to_ip_addr().unwrap(), to.to_ip_addr().unwrap());
+    }
+
+    /// Restores delivery from `from` to `to` blocked with [`NetworkHandle::block`].
+    pub fn unblock(&self, from: impl ToIpAddr, to: impl ToIpAddr) {
+        self.state()
+            .borrow_mut()
+            .topology
+            .unblock(from.to_ip_addr().unwrap(), to.to_ip_addr().unwrap());
+    }
+
+    /// Blocks links from every node of `from` to every node of `to`.
+    pub fn block_one_way<A: ToIpAddr, B: ToIpAddr>(&self, from: &[A], to: &[B]) {
+        for a in from {
+            for b in to {
+                self.block(a.to_ip_addr().unwrap(), b.to_ip_addr().unwrap());
+            }
+        }
+    }
+
+    #[deprecated(note = "use `NetworkHandle::heal`")]
     pub fn repair_all(&mut self) {
+        self.heal();
+    }
+
+    /// Adds a vertex which forwards packets between the linked vertices.
+    /// Router has no sockets and can not be used as a node address.
+    pub fn add_router(&self, addr: impl ToIpAddr) {
+        self.state()
+            .borrow_mut()
+            .topology
+            .add_router(addr.to_ip_addr().unwrap());
+    }
+
+    /// Links nodes or routers in both directions.
+    pub fn connect(&self, a: impl ToIpAddr, b: impl ToIpAddr) {
+        self.state()
+            .borrow_mut()
+            .topology
+            .connect(a.to_ip_addr().unwrap(), b.to_ip_addr().unwrap());
+    }
+
+    /// Removes links between nodes or routers in both directions.
+    pub fn disconnect(&self, a: impl ToIpAddr, b: impl ToIpAddr) {
+        self.state()
+            .borrow_mut()
+            .topology
+            .disconnect(a.to_ip_addr().unwrap(), b.to_ip_addr().unwrap());
+    }
+
+    /// By default every node is linked with all other nodes. If disabled,
+    /// links between nodes are removed and the topology is built with
+    /// [`NetworkHandle::connect`] and [`NetworkHandle::add_router`].
+    pub fn set_full_mesh(&self, enabled: bool) {
+        self.state().borrow_mut().topology.set_full_mesh(enabled);
+    }
+
+    /// Number of links on the shortest path between the nodes.
+    pub fn hops(&self, from: impl ToIpAddr, to: impl ToIpAddr) -> Option<usize> {
+        self.state().borrow().topology.hops(from, to)
+    }
+
+    /// Puts each group in its own island: links between vertices of the same group
+    /// are up and links between vertices of different groups are down.
+    /// Nodes which are not listed form one more island. Routers which are
+    /// not listed are shared by the islands, but nodes of different islands
+    /// are blocked until they are repaired or the network is healed.
+    pub fn partition<A: ToIpAddr>(&self, groups: &[&[A]]) {
+        self.state().borrow_mut().topology.partition(groups);
+    }
+
+    /// Disconnects the node from all other nodes.
+    pub fn isolate(&self, node: impl ToIpAddr) {
+        self.separate(&[node.to_ip_addr().unwrap()]);
+    }
+
+    /// Restores all links, including the one-way blocked ones.
+    pub fn heal(&self) {
         self.state().borrow_mut().topology.repair_all()
     }
 
+    /// Whether packets can be delivered between the nodes in both directions.
+    pub fn is_connected(&self, a: impl ToIpAddr, b: impl ToIpAddr) -> bool {
+        self.state()
+            .borrow()
+            .topology
+            .is_connected(a.to_ip_addr().unwrap(), b.to_ip_addr().unwrap())
+    }
+
+    /// Groups of nodes connected with each other directly or through other nodes
+    /// of the group. Nodes in the groups and the groups are sorted.
+    pub fn components(&self) -> Vec<Vec<IpAddr>> {
+        self.state().borrow().topology.components()
+    }
+
+    /// Applies to the packets sent after the call.
+    pub fn set_config(&self, config: NetworkConfig) {
+        config.validate();
+        self.state().borrow_mut().config = config;
+    }
+
+    pub fn config(&self) -> NetworkConfig {
+        self.state().borrow().config.clone()
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.state().borrow().stats.clone()
+    }
+
+    pub fn reset_stats(&self) {
+        self.state().borrow_mut().stats = Default::default();
+    }
+
+    /// Sets properties of the directed link `from -> to`.
+    pub fn set_link_config(&self, from: impl ToIpAddr, to: impl ToIpAddr, config: LinkConfig) {
+        config.validate();
+        self.state().borrow_mut().topology.set_link_config(
+            from.to_ip_addr().unwrap(),
+            to.to_ip_addr().unwrap(),
+            config,
+        );
+    }
+
+    /// Sets properties of the links in both directions
+    /// between every node of `group1` and every node of `group2`.
+    pub fn set_links_config<A: ToIpAddr, B: ToIpAddr>(
+        &self,
+        group1: &[A],
+        group2: &[B],
+        config: LinkConfig,
+    ) {
+        for a in group1 {
+            for b in group2 {
+                let a = a.to_ip_addr().unwrap();
+                let b = b.to_ip_addr().unwrap();
+                if a != b {
+                    self.set_link_config(a, b, config);
+                    self.set_link_config(b, a, config);
+                }
+            }
+        }
+    }
+
+    /// Properties of the directed link `from -> to`,
+    /// unset ones are taken from the network config.
+    pub fn link_config(&self, from: impl ToIpAddr, to: impl ToIpAddr) -> LinkConfig {
+        self.state()
+            .borrow()
+            .topology
+            .link_config(from.to_ip_addr().unwrap(), to.to_ip_addr().unwrap())
+    }
+
+    /// Adds the rule applied to the packets sent after the call.
+    /// Rules are evaluated in the order of adding, each matching rule
+    /// applies its action until the packet is dropped.
+    /// Matcher and action closures may use the network handle.
+    pub fn add_rule(&self, matcher: PacketMatcher, action: PacketAction) -> RuleHandle {
+        let state = self.state();
+        let mut state = state.borrow_mut();
+        let id = state.next_rule_id;
+        state.next_rule_id += 1;
+        state.rules.push(Rc::new(Rule::new(id, matcher, action)));
+        RuleHandle {
+            id,
+            network: self.clone(),
+        }
+    }
+
+    fn remove_rule(&self, id: u64) {
+        self.state().borrow_mut().rules.retain(|rule| rule.id != id);
+    }
+
+    fn rule_matched(&self, id: u64) -> u64 {
+        self.state()
+            .borrow()
+            .rules
+            .iter()
+            .find(|rule| rule.id == id)
+            .map_or(0, |rule| rule.matched())
+    }
+
+    /// Schedules the changes, the ones with the time in the past
+    /// are applied at the next step of the simulation.
+    /// Panics if a change refers to an unknown node, like the methods it replaces.
+    pub fn apply_schedule(&self, schedule: NetworkSchedule) {
+        let state = self.state();
+        let mut state = state.borrow_mut();
+        let now = state.clock.time();
+        for (time, change) in schedule.changes {
+            change.validate(&state.topology);
+            state.push_event(time.max(now), EventKind::Change(change));
+        }
+    }
+
+    fn apply_change(&self, change: NetworkChange) {
+        match change {
+            NetworkChange::Config(config) => self.set_config(config),
+            NetworkChange::LinkConfig { from, to, config } => {
+                self.set_link_config(from, to, config)
+            }
+            NetworkChange::LinksConfig {
+                group1,
+                group2,
+                config,
+            } => self.set_links_config(&group1, &group2, config),
+            NetworkChange::Partition(groups) => {
+                let groups = groups.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                self.partition(&groups);
+            }
+            NetworkChange::Block { from, to } => self.block(from, to),
+            NetworkChange::Unblock { from, to } => self.unblock(from, to),
+            NetworkChange::Heal => self.heal(),
+        }
+    }
+
     ////////////////////////////////////////////////////////////////////////////////
 
     pub(crate) fn next_event_timestamp(&self) -> Option<Timestamp> {
@@ -185,22 +576,36 @@ impl NetworkHandle {
     }
 
     fn handle_event(&self, event: NetworkEvent) {
-        let receiver = event.receiver;
-        if let Some(SocketData::Udp(receiver_data)) =
-            self.state().borrow_mut().registry.0.get(&receiver)
-        {
-            if let Some(receiver_data) = receiver_data.upgrade() {
-                receiver_data.borrow_mut().recv_buf.add_datagram(Datagram {
-                    from: event.sender,
-                    to: receiver,
-                    data: event.data,
-                });
-                receiver_data
-                    .borrow_mut()
-                    .recv_waiters
-                    .drain(..)
-                    .for_each(|waiter| waiter.wake());
+        match event.kind {
+            EventKind::Datagram {
+                sender,
+                receiver,
+                data,
+            } => self.deliver(sender, receiver, data),
+            EventKind::Change(change) => self.apply_change(change),
+        }
+    }
+
+    fn deliver(&self, sender: SocketAddr, receiver: SocketAddr, data: Vec<u8>) {
+        let state = self.state();
+        let receiver_data = match state.borrow().registry.0.get(&receiver) {
+            Some(SocketData::Udp(receiver_data)) => receiver_data.upgrade(),
+            _ => None,
+        };
+        if let Some(receiver_data) = receiver_data {
+            let added = receiver_data.borrow_mut().recv_buf.add_datagram(Datagram {
+                from: sender,
+                to: receiver,
+                data,
+            });
+            if added {
+                state.borrow_mut().stats.delivered += 1;
             }
+            receiver_data
+                .borrow_mut()
+                .recv_waiters
+                .drain(..)
+                .for_each(|waiter| waiter.wake());
         }
     }
 
@@ -0,0 +1,504 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Distribution of the packet delay on a single hop.
+/// Samples are capped at one year, so the delivery time does not overflow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    Constant(Duration),
+    /// Uniform in `[min, max]`.
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+    /// Normal, negative samples are clamped to zero.
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+    },
+    Exponential {
+        mean: Duration,
+    },
+    /// Pareto with the minimal value `scale`,
+    /// smaller `shape` gives heavier tail.
+    Pareto {
+        scale: Duration,
+        shape: f64,
+    },
+}
+
+impl Latency {
+    pub(crate) fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let sample = match *self {
+            Latency::Constant(delay) => delay,
+            Latency::Uniform { min, max } => rng.gen_range(min..=max),
+            Latency::Normal { mean, std_dev } => {
+                // Box-Muller transform
+                let u1 = 1.0 - rng.gen::<f64>();
+                let u2 = rng.gen::<f64>();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                secs(mean.as_secs_f64() + z * std_dev.as_secs_f64())
+            }
+            Latency::Exponential { mean } => {
+                let u = 1.0 - rng.gen::<f64>();
+                secs(-u.ln() * mean.as_secs_f64())
+            }
+            Latency::Pareto { scale, shape } => {
+                let u = 1.0 - rng.gen::<f64>();
+                secs(scale.as_secs_f64() / u.powf(1.0 / shape))
+            }
+        };
+        sample.min(MAX_LATENCY)
+    }
+
+    fn validate(&self) {
+        match *self {
+            Latency::Uniform { min, max } => {
+                assert!(min <= max, "min latency is greater than max latency")
+            }
+            Latency::Pareto { shape, .. } => {
+                assert!(shape > 0.0, "pareto shape must be positive")
+            }
+            _ => {}
+        }
+    }
+}
+
+const MAX_LATENCY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+fn secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or(MAX_LATENCY)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Two-state Markov model of the link: in the bad state packets
+/// are lost much more often than in the good one, so losses come in bursts.
+/// The state changes before every packet sent over the link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GilbertElliott {
+    pub good_to_bad: f64,
+    pub bad_to_good: f64,
+    /// Loss probability in the good state.
+    pub good_loss: f64,
+    /// Loss probability in the bad state.
+    pub bad_loss: f64,
+}
+
+impl GilbertElliott {
+    /// Mean loss probability in the long run.
+    pub fn mean_loss(&self) -> f64 {
+        let transitions = self.good_to_bad + self.bad_to_good;
+        if transitions == 0.0 {
+            return self.good_loss;
+        }
+        let bad = self.good_to_bad / transitions;
+        bad * self.bad_loss + (1.0 - bad) * self.good_loss
+    }
+}
+
+/// Loss model of the link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loss {
+    /// Every packet is lost independently with the probability.
+    Independent(f64),
+    Bursty(GilbertElliott),
+}
+
+impl Loss {
+    /// Whether the packet is lost, `bad` is the state of the link.
+    pub(crate) fn sample(&self, bad: &mut bool, rng: &mut impl Rng) -> bool {
+        match *self {
+            Loss::Independent(loss) => rng.gen_range(0.0..1.0) < loss,
+            Loss::Bursty(model) => {
+                let switch = if *bad {
+                    model.bad_to_good
+                } else {
+                    model.good_to_bad
+                };
+                if rng.gen_bool(switch) {
+                    *bad = !*bad;
+                }
+                let loss = if *bad {
+                    model.bad_loss
+                } else {
+                    model.good_loss
+                };
+                rng.gen_bool(loss)
+            }
+        }
+    }
+
+    fn validate(&self) {
+        match *self {
+            Loss::Independent(loss) => validate_probability("loss", loss),
+            Loss::Bursty(model) => {
+                validate_probability("good to bad probability", model.good_to_bad);
+                validate_probability("bad to good probability", model.bad_to_good);
+                validate_probability("good state loss", model.good_loss);
+                validate_probability("bad state loss", model.bad_loss);
+            }
+        }
+    }
+}
+
+impl From<f64> for Loss {
+    fn from(loss: f64) -> Self {
+        Loss::Independent(loss)
+    }
+}
+
+impl From<GilbertElliott> for Loss {
+    fn from(model: GilbertElliott) -> Self {
+        Loss::Bursty(model)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Packet faults applied on the whole path of the packet.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Faults {
+    /// Probability to deliver the packet twice,
+    /// the copy has its own delay.
+    pub duplication: f64,
+    /// Probability to delay the packet by extra time up to `reorder_window`,
+    /// so packets sent later can overtake it.
+    pub reordering: f64,
+    pub reorder_window: Duration,
+    /// Probability to flip a random bit of the packet.
+    pub bit_flip: f64,
+    /// Probability to cut the packet at a random position.
+    pub truncation: f64,
+}
+
+impl Faults {
+    fn validate(&self) {
+        validate_probability("duplication", self.duplication);
+        validate_probability("reordering", self.reordering);
+        validate_probability("bit flip", self.bit_flip);
+        validate_probability("truncation", self.truncation);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Properties of the simulated network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub latency: Latency,
+    pub loss: Loss,
+    /// Upper bound of the uniformly distributed extra delay.
+    pub jitter: Duration,
+    pub faults: Faults,
+    /// Link capacity in bytes per second, unlimited if not set.
+    pub bandwidth: Option<u64>,
+    /// Bytes waiting for transmission on a link, the packets over the limit
+    /// are dropped. Unlimited if not set.
+    pub queue_capacity: Option<usize>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Latency::Uniform {
+                min: Duration::from_millis(100),
+                max: Duration::from_millis(500),
+            },
+            loss: Loss::Independent(0.05),
+            jitter: Duration::ZERO,
+            faults: Faults::default(),
+            bandwidth: None,
+            queue_capacity: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Local network: sub-millisecond delays and no losses.
+    pub fn lan() -> Self {
+        Self {
+            latency: Latency::Uniform {
+                min: Duration::from_micros(100),
+                max: Duration::from_micros(500),
+            },
+            loss: Loss::Independent(0.0),
+            jitter: Duration::ZERO,
+            faults: Faults::default(),
+            bandwidth: None,
+            queue_capacity: None,
+        }
+    }
+
+    /// Wide area network: heavy-tailed delays starting from 20ms
+    /// and rare losses.
+    pub fn wan() -> Self {
+        Self {
+            latency: Latency::Pareto {
+                scale: Duration::from_millis(20),
+                shape: 3.0,
+            },
+            loss: Loss::Independent(0.01),
+            jitter: Duration::ZERO,
+            faults: Faults::default(),
+            bandwidth: None,
+            queue_capacity: None,
+        }
+    }
+
+    pub fn latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn loss(mut self, loss: impl Into<Loss>) -> Self {
+        self.loss = loss.into();
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn faults(mut self, faults: Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub fn bandwidth(mut self, bandwidth: u64) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    pub(crate) fn validate(&self) {
+        self.loss.validate();
+        self.latency.validate();
+        self.faults.validate();
+        validate_bandwidth(self.bandwidth);
+    }
+}
+
+fn validate_bandwidth(bandwidth: Option<u64>) {
+    assert!(bandwidth != Some(0), "bandwidth must be positive");
+}
+
+fn validate_probability(name: &str, value: f64) {
+    assert!(
+        (0.0..=1.0).contains(&value),
+        "{} must be in [0, 1], got {}",
+        name,
+        value
+    );
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Properties of the directed link between two nodes.
+/// Unset properties are taken from the [`NetworkConfig`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    pub latency: Option<Latency>,
+    pub loss: Option<Loss>,
+    pub jitter: Option<Duration>,
+    pub bandwidth: Option<u64>,
+    pub queue_capacity: Option<usize>,
+}
+
+impl LinkConfig {
+    pub fn latency(mut self, latency: Latency) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn loss(mut self, loss: impl Into<Loss>) -> Self {
+        self.loss = Some(loss.into());
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    pub fn bandwidth(mut self, bandwidth: u64) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    pub(crate) fn validate(&self) {
+        validate_bandwidth(self.bandwidth);
+        if let Some(loss) = self.loss {
+            loss.validate();
+        }
+        if let Some(latency) = self.latency {
+            latency.validate();
+        }
+    }
+
+    /// Fills unset properties from the network config.
+    pub(crate) fn resolve(&self, config: &NetworkConfig) -> NetworkConfig {
+        NetworkConfig {
+            latency: self.latency.unwrap_or(config.latency),
+            loss: self.loss.unwrap_or(config.loss),
+            jitter: self.jitter.unwrap_or(config.jitter),
+            faults: config.faults,
+            bandwidth: self.bandwidth.or(config.bandwidth),
+            queue_capacity: self.queue_capacity.or(config.queue_capacity),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, SeedableRng};
+    use test_case::test_case;
+
+    use super::{GilbertElliott, Latency, Loss, NetworkConfig};
+
+    fn mean(latency: Latency) -> Duration {
+        let mut rng = StdRng::seed_from_u64(123);
+        let samples = 100_000;
+        (0..samples)
+            .map(|_| latency.sample(&mut rng))
+            .sum::<Duration>()
+            / samples
+    }
+
+    fn assert_close(value: Duration, expected: Duration) {
+        let diff = value.abs_diff(expected).as_secs_f64();
+        assert!(
+            diff < expected.as_secs_f64() * 0.02,
+            "{:?} is not close to {:?}",
+            value,
+            expected
+        );
+    }
+
+    #[test_case(
+        Latency::Constant(Duration::from_millis(10)),
+        Duration::from_millis(10)
+    )]
+    #[test_case(
+        Latency::Uniform { min: Duration::from_millis(10), max: Duration::from_millis(30) },
+        Duration::from_millis(20)
+    )]
+    #[test_case(
+        Latency::Normal { mean: Duration::from_millis(50), std_dev: Duration::from_millis(5) },
+        Duration::from_millis(50)
+    )]
+    #[test_case(
+        Latency::Exponential { mean: Duration::from_millis(40) },
+        Duration::from_millis(40)
+    )]
+    #[test_case(
+        Latency::Pareto { scale: Duration::from_millis(20), shape: 3.0 },
+        Duration::from_millis(30)
+    )]
+    fn latency_mean(latency: Latency, expected: Duration) {
+        assert_close(mean(latency), expected);
+    }
+
+    #[test]
+    fn latency_bounds() {
+        let mut rng = StdRng::seed_from_u64(123);
+        let normal = Latency::Normal {
+            mean: Duration::from_millis(1),
+            std_dev: Duration::from_millis(10),
+        };
+        let pareto = Latency::Pareto {
+            scale: Duration::from_millis(20),
+            shape: 1.5,
+        };
+        for _ in 0..10_000 {
+            // negative samples are clamped instead of panicking
+            normal.sample(&mut rng);
+            assert!(pareto.sample(&mut rng) >= Duration::from_millis(20));
+        }
+        let heavy = Latency::Pareto {
+            scale: Duration::from_millis(20),
+            shape: 0.02,
+        };
+        for _ in 0..100 {
+            assert!(heavy.sample(&mut rng) <= super::MAX_LATENCY);
+        }
+        let constant = Latency::Constant(Duration::MAX);
+        assert_eq!(constant.sample(&mut rng), super::MAX_LATENCY);
+    }
+
+    #[test]
+    #[should_panic(expected = "loss must be in [0, 1]")]
+    fn bad_loss() {
+        NetworkConfig::lan().loss(1.5).validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "bandwidth must be positive")]
+    fn zero_bandwidth() {
+        NetworkConfig::lan().bandwidth(0).validate();
+    }
+
+    fn loss_runs(loss: Loss) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(123);
+        let mut bad = false;
+        let samples = 100_000;
+        let mut lost = 0;
+        let mut runs = 0;
+        let mut prev = false;
+        for _ in 0..samples {
+            let current = loss.sample(&mut bad, &mut rng);
+            if current {
+                lost += 1;
+                if !prev {
+                    runs += 1;
+                }
+            }
+            prev = current;
+        }
+        (lost as f64 / samples as f64, lost as f64 / runs as f64)
+    }
+
+    #[test]
+    fn bursty_loss() {
+        let model = GilbertElliott {
+            good_to_bad: 0.01,
+            bad_to_good: 0.1,
+            good_loss: 0.0,
+            bad_loss: 0.9,
+        };
+        assert!((model.mean_loss() - 0.9 / 11.0).abs() < 1e-9);
+
+        let (rate, run) = loss_runs(model.into());
+        assert!(
+            (rate - model.mean_loss()).abs() < 0.01,
+            "loss rate {}",
+            rate
+        );
+        assert!(run > 5.0, "mean loss run {}", run);
+
+        let (rate, run) = loss_runs(Loss::Independent(model.mean_loss()));
+        assert!(
+            (rate - model.mean_loss()).abs() < 0.01,
+            "loss rate {}",
+            rate
+        );
+        assert!(run < 1.5, "mean loss run {}", run);
+    }
+}
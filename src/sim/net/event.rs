@@ -2,18 +2,29 @@ use std::{cmp::Ordering, net::SocketAddr};
 
 use crate::time::Timestamp;
 
+use super::schedule::NetworkChange;
+
 ////////////////////////////////////////////////////////////////////////////////
 
+pub enum EventKind {
+    Datagram {
+        sender: SocketAddr,
+        receiver: SocketAddr,
+        data: Vec<u8>,
+    },
+    Change(NetworkChange),
+}
+
+/// Events with the same timestamp are ordered by `seq`.
 pub struct NetworkEvent {
     pub timestamp: Timestamp,
-    pub sender: SocketAddr,
-    pub receiver: SocketAddr,
-    pub data: Vec<u8>,
+    pub seq: u64,
+    pub kind: EventKind,
 }
 
 impl PartialEq for NetworkEvent {
     fn eq(&self, other: &Self) -> bool {
-        self.timestamp == other.timestamp
+        self.timestamp == other.timestamp && self.seq == other.seq
     }
 }
 
@@ -21,7 +32,7 @@ impl Eq for NetworkEvent {}
 
 impl Ord for NetworkEvent {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.timestamp.cmp(&self.timestamp)
+        (other.timestamp, other.seq).cmp(&(self.timestamp, self.seq))
     }
 }
 
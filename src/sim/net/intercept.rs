@@ -0,0 +1,214 @@
+use std::{cell::Cell, fmt, net::IpAddr, net::SocketAddr, rc::Rc, time::Duration};
+
+use crate::net::ip_addr::ToIpAddr;
+
+use super::NetworkHandle;
+
+////////////////////////////////////////////////////////////////////////////////
+
+type PayloadPredicate = Box<dyn Fn(&[u8]) -> bool>;
+type PayloadRewrite = Rc<dyn Fn(&mut Vec<u8>)>;
+
+/// Selects packets for the interception rule.
+/// Unset conditions match any packet.
+#[derive(Default)]
+pub struct PacketMatcher {
+    src_ip: Option<IpAddr>,
+    src_port: Option<u16>,
+    dst_ip: Option<IpAddr>,
+    dst_port: Option<u16>,
+    payload: Option<PayloadPredicate>,
+    skip: u64,
+    limit: Option<u64>,
+}
+
+impl PacketMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn src_ip(mut self, ip: impl ToIpAddr) -> Self {
+        self.src_ip = Some(ip.to_ip_addr().unwrap());
+        self
+    }
+
+    pub fn src_port(mut self, port: u16) -> Self {
+        self.src_port = Some(port);
+        self
+    }
+
+    pub fn dst_ip(mut self, ip: impl ToIpAddr) -> Self {
+        self.dst_ip = Some(ip.to_ip_addr().unwrap());
+        self
+    }
+
+    pub fn dst_port(mut self, port: u16) -> Self {
+        self.dst_port = Some(port);
+        self
+    }
+
+    pub fn payload(mut self, predicate: impl Fn(&[u8]) -> bool + 'static) -> Self {
+        self.payload = Some(Box::new(predicate));
+        self
+    }
+
+    /// Skips the first `count` packets satisfying the other conditions.
+    pub fn skip(mut self, count: u64) -> Self {
+        self.skip = count;
+        self
+    }
+
+    /// Matches at most `count` packets.
+    pub fn limit(mut self, count: u64) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Matches only the `n`-th packet satisfying the other conditions, starting from 1.
+    pub fn nth(self, n: u64) -> Self {
+        assert!(n > 0, "packets are counted from 1");
+        self.skip(n - 1).limit(1)
+    }
+
+    fn satisfies(&self, from: SocketAddr, to: SocketAddr, data: &[u8]) -> bool {
+        self.src_ip.is_none_or(|ip| ip == from.ip())
+            && self.src_port.is_none_or(|port| port == from.port())
+            && self.dst_ip.is_none_or(|ip| ip == to.ip())
+            && self.dst_port.is_none_or(|port| port == to.port())
+            && self.payload.as_ref().is_none_or(|f| f(data))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What happens with the intercepted packet.
+#[derive(Clone)]
+pub enum PacketAction {
+    Drop,
+    /// Delays the packet in addition to the link delay.
+    Delay(Duration),
+    /// Sends the given number of extra copies.
+    Duplicate(usize),
+    /// Modifies the payload, the following rules see the modified packet.
+    Rewrite(PayloadRewrite),
+}
+
+impl PacketAction {
+    pub fn rewrite(f: impl Fn(&mut Vec<u8>) + 'static) -> Self {
+        Self::Rewrite(Rc::new(f))
+    }
+}
+
+impl fmt::Debug for PacketAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drop => write!(f, "Drop"),
+            Self::Delay(delay) => f.debug_tuple("Delay").field(delay).finish(),
+            Self::Duplicate(copies) => f.debug_tuple("Duplicate").field(copies).finish(),
+            Self::Rewrite(_) => write!(f, "Rewrite"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Rules are shared with [`intercept`], so counters are in cells.
+pub(crate) struct Rule {
+    pub id: u64,
+    matcher: PacketMatcher,
+    action: PacketAction,
+    // packets satisfying the matcher conditions
+    seen: Cell<u64>,
+    matched: Cell<u64>,
+}
+
+impl Rule {
+    pub fn new(id: u64, matcher: PacketMatcher, action: PacketAction) -> Self {
+        Self {
+            id,
+            matcher,
+            action,
+            seen: Cell::new(0),
+            matched: Cell::new(0),
+        }
+    }
+
+    pub fn matched(&self) -> u64 {
+        self.matched.get()
+    }
+
+    /// Returns `false` if the packet is dropped.
+    fn apply(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+        data: &mut Vec<u8>,
+        interception: &mut Interception,
+    ) -> bool {
+        if !self.matcher.satisfies(from, to, data) {
+            return true;
+        }
+        self.seen.set(self.seen.get() + 1);
+        if self.seen.get() <= self.matcher.skip
+            || self
+                .matcher
+                .limit
+                .is_some_and(|limit| self.matched.get() >= limit)
+        {
+            return true;
+        }
+        self.matched.set(self.matched.get() + 1);
+        interception.matched = true;
+        match &self.action {
+            PacketAction::Drop => return false,
+            PacketAction::Delay(delay) => interception.delay += *delay,
+            PacketAction::Duplicate(copies) => interception.copies += copies,
+            PacketAction::Rewrite(f) => f(data),
+        }
+        true
+    }
+}
+
+/// Accumulated effect of the rules on the packet.
+#[derive(Default)]
+pub(crate) struct Interception {
+    pub matched: bool,
+    pub dropped: bool,
+    pub delay: Duration,
+    pub copies: usize,
+}
+
+/// Applies the rules in order until the packet is dropped.
+/// Must be called without borrowing the network state,
+/// so the user closures can use the network.
+pub(crate) fn intercept(
+    rules: &[Rc<Rule>],
+    from: SocketAddr,
+    to: SocketAddr,
+    data: &mut Vec<u8>,
+) -> Interception {
+    let mut interception = Interception::default();
+    interception.dropped = !rules
+        .iter()
+        .all(|rule| rule.apply(from, to, data, &mut interception));
+    interception
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Returned by [`NetworkHandle::add_rule`], the rule stays active until removed.
+pub struct RuleHandle {
+    pub(crate) id: u64,
+    pub(crate) network: NetworkHandle,
+}
+
+impl RuleHandle {
+    /// Number of packets the action was applied to.
+    pub fn matched(&self) -> u64 {
+        self.network.rule_matched(self.id)
+    }
+
+    pub fn remove(self) {
+        self.network.remove_rule(self.id);
+    }
+}
@@ -0,0 +1,123 @@
+use std::net::IpAddr;
+
+use crate::{net::ip_addr::ToIpAddr, time::Timestamp};
+
+use super::{topology::NetworkTopology, LinkConfig, NetworkConfig};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Change of the network conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkChange {
+    /// Replaces the network config.
+    Config(NetworkConfig),
+    /// Sets properties of the directed link `from -> to`.
+    LinkConfig {
+        from: IpAddr,
+        to: IpAddr,
+        config: LinkConfig,
+    },
+    /// Sets properties of the links in both directions
+    /// between every node of `group1` and every node of `group2`.
+    LinksConfig {
+        group1: Vec<IpAddr>,
+        group2: Vec<IpAddr>,
+        config: LinkConfig,
+    },
+    /// See [`NetworkHandle::partition`](super::NetworkHandle::partition).
+    Partition(Vec<Vec<IpAddr>>),
+    Block {
+        from: IpAddr,
+        to: IpAddr,
+    },
+    Unblock {
+        from: IpAddr,
+        to: IpAddr,
+    },
+    /// Restores all links.
+    Heal,
+}
+
+impl NetworkChange {
+    /// See [`NetworkHandle::set_links_config`](super::NetworkHandle::set_links_config).
+    pub fn links_config<A: ToIpAddr, B: ToIpAddr>(
+        group1: &[A],
+        group2: &[B],
+        config: LinkConfig,
+    ) -> Self {
+        Self::LinksConfig {
+            group1: to_ip_addrs(group1),
+            group2: to_ip_addrs(group2),
+            config,
+        }
+    }
+
+    pub fn partition<A: ToIpAddr>(groups: &[&[A]]) -> Self {
+        Self::Partition(groups.iter().map(|group| to_ip_addrs(group)).collect())
+    }
+
+    /// Panics if the change can not be applied to the topology.
+    pub(crate) fn validate(&self, topology: &NetworkTopology) {
+        match self {
+            Self::Config(config) => config.validate(),
+            Self::LinkConfig { from, to, config } => {
+                topology.expect_vertex(*from);
+                topology.expect_vertex(*to);
+                config.validate();
+            }
+            Self::LinksConfig {
+                group1,
+                group2,
+                config,
+            } => {
+                for addr in group1.iter().chain(group2) {
+                    topology.expect_vertex(*addr);
+                }
+                config.validate();
+            }
+            Self::Partition(groups) => {
+                let groups = groups.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                topology.islands(&groups);
+            }
+            Self::Block { from, to } | Self::Unblock { from, to } => {
+                topology.expect_vertex(*from);
+                topology.expect_vertex(*to);
+            }
+            Self::Heal => {}
+        }
+    }
+}
+
+fn to_ip_addrs<A: ToIpAddr>(addrs: &[A]) -> Vec<IpAddr> {
+    addrs.iter().map(|a| a.to_ip_addr().unwrap()).collect()
+}
+
+impl From<NetworkConfig> for NetworkChange {
+    fn from(config: NetworkConfig) -> Self {
+        Self::Config(config)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Network changes at the given moments of the global time.
+///
+/// Applied with [`NetworkHandle::apply_schedule`](super::NetworkHandle::apply_schedule),
+/// changes happen exactly at their time, changes with the same time
+/// are applied in the order they were added.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NetworkSchedule {
+    pub(crate) changes: Vec<(Timestamp, NetworkChange)>,
+}
+
+impl NetworkSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the change at the time `time` since the start of the simulation.
+    pub fn at(mut self, time: Timestamp, change: impl Into<NetworkChange>) -> Self {
+        self.changes.push((time, change.into()));
+        self
+    }
+}
@@ -0,0 +1,26 @@
+////////////////////////////////////////////////////////////////////////////////
+
+/// Counters of the packets passed through the network.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Packets sent to the alive sockets.
+    pub sent: u64,
+    /// Packets put into the receive buffers, including duplicates.
+    pub delivered: u64,
+    /// Packets lost because of the link loss.
+    pub lost: u64,
+    /// Packets dropped because there is no route to the receiver.
+    pub unreachable: u64,
+    /// Packets dropped because the link queue is full.
+    pub overflowed: u64,
+    /// Packets matched by the interception rules.
+    pub intercepted: u64,
+    /// Packets delivered twice.
+    pub duplicated: u64,
+    /// Packets delayed within the reorder window.
+    pub reordered: u64,
+    /// Packets with a flipped bit.
+    pub bit_flipped: u64,
+    /// Packets cut at a random position.
+    pub truncated: u64,
+}
@@ -1,6 +1,15 @@
-use std::net::SocketAddr;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    rc::Rc,
+    time::Duration,
+};
 
-use crate::sim::{node::NodeBuilder, Sim};
+use crate::sim::{
+    node::NodeBuilder, now, sleep, timeout, Faults, GilbertElliott, Latency, LinkConfig, Loss,
+    NetworkChange, NetworkConfig, NetworkSchedule, NetworkStats, PacketAction, PacketMatcher, Sim,
+};
 
 use super::UdpSocket;
 
@@ -37,3 +46,948 @@ fn network_split_udp() {
     node2.make_steps(None);
     node1.make_steps(None);
 }
+
+#[test]
+fn network_split_scenario() {
+    let mut sim = Sim::new(321);
+    let node1 = NodeBuilder::with_ip("10.12.1.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let node2 = NodeBuilder::with_ip("10.13.1.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let received = Rc::new(Cell::new(0));
+    let receiver = node1.spawn({
+        let received = received.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:123").unwrap();
+            let mut buf = [0u8; 10];
+            loop {
+                socket.recv_from(&mut buf).await;
+                received.set(received.get() + 1);
+            }
+        }
+    });
+    let send = |count: usize| {
+        let target = SocketAddr::new(node1.ip(), 123);
+        node2.spawn(async move {
+            let socket = UdpSocket::bind("0.0.0.0:123").unwrap();
+            for _ in 0..count {
+                socket.send_to(b"hello", target).unwrap();
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+    };
+
+    sim.block_on(async {
+        sim.network().separate(&[node1.ip()]);
+        send(10).await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(received.get(), 0);
+
+        sim.network().repair(&[node1.ip(), node2.ip()]);
+        send(10).await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+        assert!(received.get() > 0);
+
+        sim.crash(node1.ip());
+        assert!(receiver.await.unwrap_err().is_cancelled());
+    });
+}
+
+#[test]
+fn network_config() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config.clone());
+    assert_eq!(sim.network().config(), config);
+    let node1 = NodeBuilder::with_ip("10.12.1.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let node2 = NodeBuilder::with_ip("10.13.1.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let arrivals = Rc::new(RefCell::new(Vec::new()));
+    node1.spawn({
+        let arrivals = arrivals.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:123").unwrap();
+            let mut buf = [0u8; 10];
+            loop {
+                socket.recv_from(&mut buf).await;
+                arrivals.borrow_mut().push(now());
+            }
+        }
+    });
+    let send = |count: usize| {
+        let target = SocketAddr::new(node1.ip(), 123);
+        node2.spawn(async move {
+            let socket = UdpSocket::bind("0.0.0.0:123").unwrap();
+            for _ in 0..count {
+                socket.send_to(b"hello", target).unwrap();
+                sleep(Duration::from_millis(1)).await;
+            }
+        })
+    };
+
+    sim.block_on(async {
+        send(1000).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        let arrivals = arrivals.borrow();
+        assert_eq!(arrivals.len(), 1000);
+        assert_eq!(arrivals[0], Duration::from_millis(10));
+        assert_eq!(arrivals[999], Duration::from_millis(1009));
+    });
+
+    sim.network().set_config(NetworkConfig::lan().loss(1.0));
+    sim.block_on(async {
+        send(100).await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+    });
+    assert_eq!(arrivals.borrow().len(), 1000);
+}
+
+#[test]
+fn link_config() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(1)));
+    let mut sim = Sim::with_network_config(123, config);
+    let ips = ["10.0.0.1", "10.0.0.2", "10.1.0.1"];
+    for ip in ips {
+        NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
+    }
+    let net = sim.network();
+    // second data center
+    net.set_links_config(
+        &ips[..2],
+        &ips[2..],
+        LinkConfig::default().latency(Latency::Constant(Duration::from_millis(80))),
+    );
+    // flaky outgoing link
+    net.set_link_config(ips[0], ips[1], LinkConfig::default().loss(0.3));
+    assert_eq!(net.link_config(ips[1], ips[0]), LinkConfig::default());
+
+    let arrivals = Rc::new(RefCell::new(Vec::new()));
+    for ip in ips {
+        sim.node(ip).unwrap().spawn({
+            let arrivals = arrivals.clone();
+            async move {
+                let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+                let mut buf = [0u8; 10];
+                loop {
+                    let (_, from) = socket.recv_from(&mut buf).await;
+                    arrivals
+                        .borrow_mut()
+                        .push((from.ip(), socket.local_addr().ip(), now()));
+                }
+            }
+        });
+    }
+    let send = |from: &str, to: &str, count: usize| {
+        let target = SocketAddr::new(to.parse().unwrap(), 1);
+        sim.node(from).unwrap().spawn(async move {
+            let socket = UdpSocket::bind("0.0.0.0:2").unwrap();
+            for _ in 0..count {
+                socket.send_to(b"hello", target).unwrap();
+            }
+        })
+    };
+    let received = |from: &str, to: &str| {
+        let from: IpAddr = from.parse().unwrap();
+        let to: IpAddr = to.parse().unwrap();
+        arrivals
+            .borrow()
+            .iter()
+            .filter(|(f, t, _)| *f == from && *t == to)
+            .map(|(_, _, time)| *time)
+            .collect::<Vec<_>>()
+    };
+
+    sim.block_on(async {
+        send(ips[0], ips[1], 500).await.unwrap();
+        send(ips[1], ips[0], 500).await.unwrap();
+        send(ips[1], ips[2], 10).await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+    });
+    let lossy = received(ips[0], ips[1]).len();
+    assert!((300..400).contains(&lossy), "{} packets received", lossy);
+    assert_eq!(received(ips[1], ips[0]).len(), 500);
+    assert_eq!(
+        received(ips[1], ips[2]),
+        vec![Duration::from_millis(80); 10]
+    );
+
+    // properties are kept while the link is down
+    net.separate(&[ips[0]]);
+    net.repair(&ips);
+    assert_eq!(
+        net.link_config(ips[0], ips[1]).loss,
+        Some(Loss::Independent(0.3))
+    );
+}
+
+#[test]
+fn jitter() {
+    let config = NetworkConfig::lan()
+        .latency(Latency::Constant(Duration::from_millis(10)))
+        .jitter(Duration::from_millis(5));
+    let mut sim = Sim::with_network_config(123, config);
+    let node1 = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let node2 = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let arrivals = Rc::new(RefCell::new(Vec::new()));
+    node1.spawn({
+        let arrivals = arrivals.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 10];
+            loop {
+                socket.recv_from(&mut buf).await;
+                arrivals.borrow_mut().push(now());
+            }
+        }
+    });
+    node2.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for _ in 0..100 {
+            socket.send_to(b"hello", "10.0.0.1:1").unwrap();
+        }
+    });
+    sim.run_for(Duration::from_secs(1));
+    let arrivals = arrivals.borrow();
+    assert_eq!(arrivals.len(), 100);
+    assert!(arrivals
+        .iter()
+        .all(|t| (Duration::from_millis(10)..=Duration::from_millis(15)).contains(t)));
+    assert!(arrivals.iter().any(|t| *t != arrivals[0]));
+}
+
+#[test]
+fn one_way_partition() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(1)));
+    let mut sim = Sim::with_network_config(123, config);
+    let ips = ["10.0.0.1", "10.0.0.2", "10.0.0.3"];
+    let received = Rc::new(RefCell::new(Vec::new()));
+    for ip in ips {
+        NodeBuilder::with_ip(ip)
+            .unwrap()
+            .build(&mut sim)
+            .unwrap()
+            .spawn({
+                let received = received.clone();
+                async move {
+                    let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+                    let mut buf = [0u8; 10];
+                    loop {
+                        let (_, from) = socket.recv_from(&mut buf).await;
+                        received
+                            .borrow_mut()
+                            .push((from.ip(), socket.local_addr().ip()));
+                    }
+                }
+            });
+    }
+    let exchange = || {
+        received.borrow_mut().clear();
+        sim.block_on(async {
+            for from in ips {
+                sim.node(from).unwrap().spawn(async move {
+                    let socket = UdpSocket::bind("0.0.0.0:2").unwrap();
+                    for to in ips {
+                        if to != from {
+                            socket
+                                .send_to(b"ping", SocketAddr::new(to.parse().unwrap(), 1))
+                                .unwrap();
+                        }
+                    }
+                });
+            }
+            sleep(Duration::from_secs(1)).await;
+        });
+        let mut received = received.borrow().clone();
+        received.sort();
+        received
+    };
+    let ip = |i: usize| ips[i].parse::<IpAddr>().unwrap();
+
+    // leader can send but not receive
+    sim.network().block_one_way(&ips[1..], &ips[..1]);
+    assert_eq!(
+        exchange(),
+        vec![
+            (ip(0), ip(1)),
+            (ip(0), ip(2)),
+            (ip(1), ip(2)),
+            (ip(2), ip(1))
+        ]
+    );
+
+    sim.network().unblock(ips[1], ips[0]);
+    sim.network().block(ips[1], ips[2]);
+    assert_eq!(
+        exchange(),
+        vec![
+            (ip(0), ip(1)),
+            (ip(0), ip(2)),
+            (ip(1), ip(0)),
+            (ip(2), ip(1))
+        ]
+    );
+}
+
+#[test]
+fn partition_shape() {
+    let mut sim = Sim::new(123);
+    let ips = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"];
+    for ip in ips {
+        NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
+    }
+    let ip = |i: usize| ips[i].parse::<IpAddr>().unwrap();
+    let net = sim.network();
+
+    net.partition(&[&ips[..1], &ips[1..3], &ips[3..]]);
+    assert_eq!(
+        net.components(),
+        vec![vec![ip(0)], vec![ip(1), ip(2)], vec![ip(3)]]
+    );
+
+    net.heal();
+    net.isolate(ips[2]);
+    assert!(net.is_connected(ips[0], ips[1]));
+    assert!(!net.is_connected(ips[0], ips[2]));
+    assert_eq!(
+        net.components(),
+        vec![vec![ip(0), ip(1), ip(3)], vec![ip(2)]]
+    );
+
+    net.heal();
+    assert_eq!(net.components(), vec![(0..4).map(ip).collect::<Vec<_>>()]);
+}
+
+#[test]
+fn routed_topology() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config);
+    let net = sim.network();
+    net.set_full_mesh(false);
+    net.add_router("10.0.1.1");
+    net.add_router("10.0.2.1");
+    let ips = ["10.0.1.2", "10.0.1.3", "10.0.2.2"];
+    for ip in ips {
+        NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
+    }
+    net.connect(ips[0], "10.0.1.1");
+    net.connect(ips[1], "10.0.1.1");
+    net.connect(ips[2], "10.0.2.1");
+    net.connect("10.0.1.1", "10.0.2.1");
+    net.set_link_config(
+        "10.0.1.1",
+        "10.0.2.1",
+        LinkConfig::default().latency(Latency::Constant(Duration::from_millis(80))),
+    );
+    assert_eq!(net.hops(ips[0], ips[1]), Some(2));
+    assert_eq!(net.hops(ips[0], ips[2]), Some(3));
+
+    let ping = |from: &'static str, to: &'static str| {
+        let receiver = sim.node(to).unwrap().spawn(async {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 10];
+            socket.recv_from(&mut buf).await;
+        });
+        sim.block_on(async {
+            // let the receiver bind the socket
+            sleep(Duration::from_millis(1)).await;
+            let start = now();
+            sim.node(from).unwrap().spawn(async move {
+                let socket = UdpSocket::bind("0.0.0.0:2").unwrap();
+                socket
+                    .send_to(b"ping", SocketAddr::new(to.parse().unwrap(), 1))
+                    .unwrap();
+            });
+            match timeout(Duration::from_secs(1), receiver).await {
+                Ok(result) => {
+                    result.unwrap();
+                    Some(now() - start)
+                }
+                Err(_) => None,
+            }
+        })
+    };
+    assert_eq!(ping(ips[0], ips[1]), Some(Duration::from_millis(20)));
+    assert_eq!(ping(ips[0], ips[2]), Some(Duration::from_millis(100)));
+
+    // failed switch partitions the nodes behind it
+    net.isolate("10.0.2.1");
+    assert_eq!(ping(ips[0], ips[2]), None);
+    assert_eq!(ping(ips[1], ips[0]), Some(Duration::from_millis(20)));
+    assert_eq!(net.components().len(), 2);
+}
+
+fn run_faults(faults: Faults) -> (Vec<Vec<u8>>, NetworkStats) {
+    let config = NetworkConfig::lan()
+        .latency(Latency::Constant(Duration::from_millis(10)))
+        .faults(faults);
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .udp_recv_buffer_size(1 << 20)
+        .build(&mut sim)
+        .unwrap();
+    let sender = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let received = Rc::new(RefCell::new(Vec::new()));
+    receiver.spawn({
+        let received = received.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 10];
+            loop {
+                let (len, _) = socket.recv_from(&mut buf).await;
+                received.borrow_mut().push(buf[..len].to_vec());
+            }
+        }
+    });
+    sender.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for i in 0..100u32 {
+            socket.send_to(&i.to_be_bytes(), "10.0.0.1:1").unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(1));
+    let stats = sim.network().stats();
+    let received = received.take();
+    (received, stats)
+}
+
+#[test]
+fn heavy_tail_latency() {
+    let config = NetworkConfig::lan().latency(Latency::Pareto {
+        scale: Duration::from_millis(20),
+        shape: 0.02,
+    });
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let sender = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    receiver.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        let mut buf = [0u8; 4];
+        loop {
+            socket.recv_from(&mut buf).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(1));
+    // delivery time does not overflow
+    sender.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for i in 0..100u32 {
+            socket.send_to(&i.to_be_bytes(), "10.0.0.1:1").unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(sim.network().stats().sent, 100);
+}
+
+#[test]
+fn duplication() {
+    let (received, stats) = run_faults(Faults {
+        duplication: 0.5,
+        ..Default::default()
+    });
+    assert_eq!(stats.sent, 100);
+    assert!((30..70).contains(&stats.duplicated));
+    assert_eq!(stats.delivered, 100 + stats.duplicated);
+    assert_eq!(received.len() as u64, stats.delivered);
+    let unique = received.iter().collect::<HashSet<_>>();
+    assert_eq!(unique.len(), 100);
+}
+
+#[test]
+fn reordering() {
+    let (received, stats) = run_faults(Faults::default());
+    assert_eq!(stats.reordered, 0);
+    assert!(received.windows(2).all(|w| w[0] < w[1]));
+
+    let (received, stats) = run_faults(Faults {
+        reordering: 0.3,
+        reorder_window: Duration::from_millis(50),
+        ..Default::default()
+    });
+    assert!(stats.reordered > 0);
+    assert_eq!(received.len(), 100);
+    assert!(received.windows(2).any(|w| w[0] > w[1]));
+}
+
+#[test]
+fn corruption() {
+    let expected = (0..100u32)
+        .map(|i| i.to_be_bytes().to_vec())
+        .collect::<Vec<_>>();
+
+    let (received, stats) = run_faults(Faults {
+        bit_flip: 1.0,
+        ..Default::default()
+    });
+    assert_eq!(stats.bit_flipped, 100);
+    for (got, sent) in received.iter().zip(expected.iter()) {
+        let flipped = got
+            .iter()
+            .zip(sent.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>();
+        assert_eq!(flipped, 1);
+    }
+
+    let (received, stats) = run_faults(Faults {
+        truncation: 1.0,
+        ..Default::default()
+    });
+    assert_eq!(stats.truncated, 100);
+    for (got, sent) in received.iter().zip(expected.iter()) {
+        assert!(got.len() < sent.len());
+        assert!(sent.starts_with(got));
+    }
+}
+
+#[test]
+fn bursty_link_loss() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .udp_recv_buffer_size(1 << 20)
+        .build(&mut sim)
+        .unwrap();
+    let sender = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    sim.network().set_link_config(
+        "10.0.0.2",
+        "10.0.0.1",
+        LinkConfig::default().loss(GilbertElliott {
+            good_to_bad: 0.05,
+            bad_to_good: 0.2,
+            good_loss: 0.0,
+            bad_loss: 1.0,
+        }),
+    );
+    let received = Rc::new(RefCell::new(Vec::new()));
+    receiver.spawn({
+        let received = received.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 4];
+            loop {
+                socket.recv_from(&mut buf).await;
+                received.borrow_mut().push(u32::from_be_bytes(buf));
+            }
+        }
+    });
+    sender.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for i in 0..1000u32 {
+            socket.send_to(&i.to_be_bytes(), "10.0.0.1:1").unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(2));
+
+    let received = received.borrow();
+    let stats = sim.network().stats();
+    assert_eq!(stats.lost + received.len() as u64, 1000);
+    assert!((100..300).contains(&stats.lost), "{} lost", stats.lost);
+    // losses come in bursts
+    let gaps = received.windows(2).filter(|w| w[1] - w[0] > 1).count();
+    assert!((gaps as u64) < stats.lost / 2);
+}
+
+/// Sends ten packets of 100 bytes from 10.0.0.2 to 10.0.0.1, waiting `interval`
+/// after each one, and returns their arrival times.
+fn run_bandwidth(
+    config: NetworkConfig,
+    link: LinkConfig,
+    interval: Duration,
+) -> (Vec<Duration>, NetworkStats) {
+    let config = config.latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let sender = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    sim.network().set_link_config("10.0.0.2", "10.0.0.1", link);
+    let arrivals = Rc::new(RefCell::new(Vec::new()));
+    receiver.spawn({
+        let arrivals = arrivals.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 100];
+            loop {
+                socket.recv_from(&mut buf).await;
+                arrivals.borrow_mut().push(now());
+            }
+        }
+    });
+    sender.spawn(async move {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for _ in 0..10 {
+            socket.send_to(&[0u8; 100], "10.0.0.1:1").unwrap();
+            if !interval.is_zero() {
+                sleep(interval).await;
+            }
+        }
+    });
+    sim.run_for(Duration::from_secs(2));
+    let stats = sim.network().stats();
+    let arrivals = arrivals.take();
+    (arrivals, stats)
+}
+
+#[test]
+fn bandwidth() {
+    // back to back
+    let (arrivals, stats) = run_bandwidth(
+        NetworkConfig::lan(),
+        LinkConfig::default().bandwidth(1000),
+        Duration::ZERO,
+    );
+    assert_eq!(stats.delivered, 10);
+    // 100ms to transmit each packet
+    let expected = (1..=10)
+        .map(|i| Duration::from_millis(100 * i + 10))
+        .collect::<Vec<_>>();
+    assert_eq!(arrivals, expected);
+}
+
+#[test]
+fn queue_overflow() {
+    let (arrivals, stats) = run_bandwidth(
+        NetworkConfig::lan(),
+        LinkConfig::default().bandwidth(1000).queue_capacity(300),
+        Duration::ZERO,
+    );
+    assert_eq!(stats.sent, 10);
+    assert_eq!(stats.delivered, 3);
+    assert_eq!(stats.overflowed, 7);
+    assert_eq!(arrivals.last(), Some(&Duration::from_millis(310)));
+
+    // copies do not fit in the queue behind the originals
+    let faults = Faults {
+        duplication: 1.0,
+        ..Default::default()
+    };
+    let (arrivals, stats) = run_bandwidth(
+        NetworkConfig::lan().faults(faults),
+        LinkConfig::default().bandwidth(1000).queue_capacity(100),
+        Duration::from_millis(100),
+    );
+    assert_eq!(arrivals.len(), 10);
+    assert_eq!(stats.duplicated, 0);
+    assert_eq!(stats.overflowed, 10);
+}
+
+#[test]
+fn queue_drains() {
+    // paced at the link rate, limits are taken from the network config
+    let (arrivals, stats) = run_bandwidth(
+        NetworkConfig::lan().bandwidth(1000).queue_capacity(100),
+        LinkConfig::default(),
+        Duration::from_millis(100),
+    );
+    assert_eq!(arrivals.len(), 10);
+    assert_eq!(stats.overflowed, 0);
+    assert_eq!(arrivals[9], Duration::from_millis(1010));
+
+    // back to back, all but the first one overflow
+    let (arrivals, stats) = run_bandwidth(
+        NetworkConfig::lan().bandwidth(1000).queue_capacity(100),
+        LinkConfig::default(),
+        Duration::ZERO,
+    );
+    assert_eq!(arrivals.len(), 1);
+    assert_eq!(stats.overflowed, 9);
+}
+
+#[test]
+fn network_schedule() {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let sender = NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    sim.network().apply_schedule(
+        NetworkSchedule::new()
+            .at(
+                Duration::from_secs(1),
+                NetworkChange::links_config(
+                    &["10.0.0.1"],
+                    &["10.0.0.2"],
+                    LinkConfig::default().latency(Latency::Constant(Duration::from_millis(500))),
+                ),
+            )
+            .at(
+                Duration::from_secs(2),
+                NetworkChange::partition(&[&["10.0.0.1"], &["10.0.0.2"]]),
+            )
+            .at(Duration::from_secs(3), NetworkChange::Heal)
+            .at(
+                Duration::from_secs(3),
+                NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(20))),
+            ),
+    );
+    let arrivals = Rc::new(RefCell::new(Vec::new()));
+    receiver.spawn({
+        let arrivals = arrivals.clone();
+        async move {
+            let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+            let mut buf = [0u8; 4];
+            loop {
+                socket.recv_from(&mut buf).await;
+                arrivals.borrow_mut().push((u32::from_be_bytes(buf), now()));
+            }
+        }
+    });
+    sender.spawn(async {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for i in 0..40u32 {
+            socket.send_to(&i.to_be_bytes(), "10.0.0.1:1").unwrap();
+            sleep(Duration::from_millis(100)).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(5));
+
+    let arrivals = arrivals.borrow();
+    let delay = |i: u32| {
+        arrivals
+            .iter()
+            .find(|(j, _)| *j == i)
+            .map(|(_, time)| *time - Duration::from_millis(100 * i as u64))
+    };
+    assert_eq!(delay(9), Some(Duration::from_millis(10)));
+    // link config overrides the network config
+    assert_eq!(delay(10), Some(Duration::from_millis(500)));
+    assert_eq!(delay(19), Some(Duration::from_millis(500)));
+    assert_eq!(delay(20), None);
+    assert_eq!(delay(29), None);
+    assert_eq!(delay(30), Some(Duration::from_millis(500)));
+    assert_eq!(arrivals.len(), 30);
+    assert_eq!(sim.network().stats().unreachable, 10);
+}
+
+#[test]
+fn network_schedule_past() {
+    let mut sim = Sim::with_network_config(123, NetworkConfig::lan());
+    NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    sim.run_for(Duration::from_secs(1));
+    sim.network().apply_schedule(NetworkSchedule::new().at(
+        Duration::ZERO,
+        NetworkChange::partition(&[&["10.0.0.1"], &["10.0.0.2"]]),
+    ));
+    assert!(sim.network().is_connected("10.0.0.1", "10.0.0.2"));
+    sim.next_step();
+    assert!(!sim.network().is_connected("10.0.0.1", "10.0.0.2"));
+    assert_eq!(sim.time(), Duration::from_secs(1));
+}
+
+#[test]
+#[should_panic(expected = "node '10.0.0.3' is not registered")]
+fn network_schedule_unknown_node() {
+    let mut sim = Sim::with_network_config(123, NetworkConfig::lan());
+    NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    sim.network().apply_schedule(NetworkSchedule::new().at(
+        Duration::from_secs(1),
+        NetworkChange::Block {
+            from: "10.0.0.1".parse().unwrap(),
+            to: "10.0.0.3".parse().unwrap(),
+        },
+    ));
+}
+
+#[test]
+#[should_panic(expected = "node '10.0.0.1' is listed in several groups")]
+fn network_schedule_invalid_partition() {
+    let mut sim = Sim::with_network_config(123, NetworkConfig::lan());
+    for ip in ["10.0.0.1", "10.0.0.2"] {
+        NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
+    }
+    sim.network().apply_schedule(NetworkSchedule::new().at(
+        Duration::from_secs(1),
+        NetworkChange::partition(&[&["10.0.0.1"], &["10.0.0.2", "10.0.0.1"]]),
+    ));
+}
+
+type Received = Rc<RefCell<Vec<(u16, Vec<u8>, Duration)>>>;
+
+/// Node 10.0.0.1 receives packets on ports 1 and 2.
+fn rules_sim() -> (Sim, Received) {
+    let config = NetworkConfig::lan().latency(Latency::Constant(Duration::from_millis(10)));
+    let mut sim = Sim::with_network_config(123, config);
+    let receiver = NodeBuilder::with_ip("10.0.0.1")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    NodeBuilder::with_ip("10.0.0.2")
+        .unwrap()
+        .build(&mut sim)
+        .unwrap();
+    let received = Received::default();
+    for port in [1, 2] {
+        receiver.spawn({
+            let received = received.clone();
+            async move {
+                let socket = UdpSocket::bind(("0.0.0.0", port)).unwrap();
+                let mut buf = [0u8; 10];
+                loop {
+                    let (len, _) = socket.recv_from(&mut buf).await;
+                    received
+                        .borrow_mut()
+                        .push((port, buf[..len].to_vec(), now()));
+                }
+            }
+        });
+    }
+    sim.make_steps();
+    (sim, received)
+}
+
+/// Sends packets `[kind, i]` for `i` in `0..10` from 10.0.0.2 to ports 1 and 2 of 10.0.0.1,
+/// returns the received packets with the port and arrival time since the first send.
+fn run_rules(sim: &Sim, received: &Received, kind: u8) -> Vec<(u16, Vec<u8>, Duration)> {
+    let start = sim.time();
+    sim.node("10.0.0.2").unwrap().spawn(async move {
+        let socket = UdpSocket::bind("0.0.0.0:1").unwrap();
+        for i in 0..10u8 {
+            socket.send_to(&[kind, i], "10.0.0.1:1").unwrap();
+            socket.send_to(&[kind, i], "10.0.0.1:2").unwrap();
+            sleep(Duration::from_millis(100)).await;
+        }
+    });
+    sim.run_for(Duration::from_secs(10));
+    let mut received = received.take();
+    for (_, _, time) in received.iter_mut() {
+        *time -= start;
+    }
+    received
+}
+
+#[test]
+fn drop_rule() {
+    let (sim, received) = rules_sim();
+    let rule = sim.network().add_rule(
+        PacketMatcher::new()
+            .src_ip("10.0.0.2")
+            .dst_port(2)
+            .payload(|data| data[0] == 3),
+        PacketAction::Drop,
+    );
+    let packets = run_rules(&sim, &received, 3);
+    assert_eq!(packets.len(), 10);
+    assert!(packets.iter().all(|(port, _, _)| *port == 1));
+    assert_eq!(rule.matched(), 10);
+    let stats = sim.network().stats();
+    assert_eq!(stats.intercepted, 10);
+    assert_eq!(stats.lost, 0);
+
+    // payload does not match
+    let packets = run_rules(&sim, &received, 4);
+    assert_eq!(packets.len(), 20);
+    assert_eq!(rule.matched(), 10);
+
+    rule.remove();
+    let packets = run_rules(&sim, &received, 3);
+    assert_eq!(packets.len(), 20);
+}
+
+#[test]
+fn nth_packet_rules() {
+    let (sim, received) = rules_sim();
+    sim.network().add_rule(
+        PacketMatcher::new().dst_port(1).nth(5),
+        PacketAction::Delay(Duration::from_secs(3)),
+    );
+    sim.network().add_rule(
+        PacketMatcher::new().dst_port(2).skip(7),
+        PacketAction::Duplicate(2),
+    );
+    sim.network().add_rule(
+        PacketMatcher::new().dst_port(2).limit(1),
+        PacketAction::rewrite(|data| data.push(42)),
+    );
+    let packets = run_rules(&sim, &received, 0);
+
+    let port1 = packets
+        .iter()
+        .filter(|(port, _, _)| *port == 1)
+        .map(|(_, data, time)| (data[1], *time))
+        .collect::<Vec<_>>();
+    assert_eq!(port1.len(), 10);
+    assert_eq!(port1.last(), Some(&(4, Duration::from_millis(3410))));
+    assert!(port1[..9]
+        .iter()
+        .all(|(i, time)| *time == Duration::from_millis(100 * *i as u64 + 10)));
+
+    let port2 = packets
+        .iter()
+        .filter(|(port, _, _)| *port == 2)
+        .map(|(_, data, _)| data.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(port2.len(), 16);
+    assert_eq!(port2[0], vec![0, 0, 42]);
+    assert_eq!(port2.iter().filter(|data| data[1] == 7).count(), 3);
+    assert_eq!(port2.iter().filter(|data| data[1] == 6).count(), 1);
+    assert_eq!(sim.network().stats().duplicated, 6);
+}
+
+#[test]
+fn rule_uses_network() {
+    let (sim, received) = rules_sim();
+    let network = sim.network();
+    // drops every second packet sent
+    sim.network().add_rule(
+        PacketMatcher::new().payload(move |_| network.stats().sent.is_multiple_of(2)),
+        PacketAction::Drop,
+    );
+    let network = sim.network();
+    sim.network().add_rule(
+        PacketMatcher::new(),
+        PacketAction::rewrite(move |data| data.push(network.stats().intercepted as u8)),
+    );
+    let packets = run_rules(&sim, &received, 0);
+    assert_eq!(packets.len(), 10);
+    assert!(packets.iter().all(|(port, _, _)| *port == 1));
+}
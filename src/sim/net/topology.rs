@@ -1,11 +1,39 @@
-use std::{collections::HashSet, net::IpAddr};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    net::IpAddr,
+};
 
 use crate::net::ip_addr::ToIpAddr;
 
-#[derive(Default)]
+use super::config::LinkConfig;
+
+/// Graph of nodes and routers connected with directed links.
+/// Packets are forwarded along the shortest path of the links which are up,
+/// only routers forward packets of other vertices.
 pub(crate) struct NetworkTopology {
-    links: HashSet<(IpAddr, IpAddr)>,
-    nodes: HashSet<IpAddr>,
+    // from -> to -> whether the link is up
+    links: BTreeMap<IpAddr, BTreeMap<IpAddr, bool>>,
+    // properties are kept while the link is down or removed
+    configs: HashMap<(IpAddr, IpAddr), LinkConfig>,
+    nodes: BTreeSet<IpAddr>,
+    routers: BTreeSet<IpAddr>,
+    // registered nodes are linked with all other nodes
+    full_mesh: bool,
+    // pairs of nodes which can not send packets whatever the route is
+    blocked: HashSet<(IpAddr, IpAddr)>,
+}
+
+impl Default for NetworkTopology {
+    fn default() -> Self {
+        Self {
+            links: Default::default(),
+            configs: Default::default(),
+            nodes: Default::default(),
+            routers: Default::default(),
+            full_mesh: true,
+            blocked: Default::default(),
+        }
+    }
 }
 
 impl NetworkTopology {
@@ -15,69 +43,274 @@ impl NetworkTopology {
 
     pub fn register_node(&mut self, addr: impl ToIpAddr) {
         let addr = addr.to_ip_addr().unwrap();
+        assert!(
+            !self.routers.contains(&addr),
+            "address '{}' is used by a router",
+            addr
+        );
         self.nodes.insert(addr);
-        for other in self.nodes.iter() {
-            self.links.insert((addr, *other));
-            self.links.insert((*other, addr));
+        if self.full_mesh {
+            for other in self.nodes.clone() {
+                self.add_link(addr, other);
+                self.add_link(other, addr);
+            }
         }
     }
 
-    pub fn separate<A: ToIpAddr>(&mut self, group: &[A]) {
-        let mut sep_nodes = group
-            .iter()
-            .map(|a| a.to_ip_addr().unwrap())
-            .collect::<Vec<_>>();
-        sep_nodes.sort();
-        for sep_node in sep_nodes.iter() {
-            if !self.nodes.contains(sep_node) {
-                panic!("node '{}' is not registered", sep_node);
-            }
-            for other in self.nodes.iter() {
-                if sep_nodes.binary_search(other).is_err() {
-                    self.links.remove(&(*sep_node, *other));
-                    self.links.remove(&(*other, *sep_node));
+    pub fn add_router(&mut self, addr: IpAddr) {
+        assert!(
+            !self.nodes.contains(&addr),
+            "address '{}' is used by a node",
+            addr
+        );
+        self.routers.insert(addr);
+    }
+
+    /// Enabling links every pair of nodes,
+    /// disabling removes links between nodes.
+    pub fn set_full_mesh(&mut self, enabled: bool) {
+        self.full_mesh = enabled;
+        for a in self.nodes.clone() {
+            for b in self.nodes.clone() {
+                if a == b {
+                    continue;
+                }
+                if enabled {
+                    self.add_link(a, b);
+                } else {
+                    self.remove_link(a, b);
                 }
             }
         }
     }
 
+    /// Links the vertices in both directions.
+    pub fn connect(&mut self, a: IpAddr, b: IpAddr) {
+        self.expect_vertex(a);
+        self.expect_vertex(b);
+        self.add_link(a, b);
+        self.add_link(b, a);
+    }
+
+    /// Removes links between the vertices in both directions.
+    pub fn disconnect(&mut self, a: IpAddr, b: IpAddr) {
+        self.remove_link(a, b);
+        self.remove_link(b, a);
+    }
+
+    pub fn separate<A: ToIpAddr>(&mut self, group: &[A]) {
+        let group = group
+            .iter()
+            .map(|a| self.expect_vertex(a.to_ip_addr().unwrap()))
+            .collect::<BTreeSet<_>>();
+        self.for_each_link(|from, to, up| {
+            if group.contains(&from) != group.contains(&to) {
+                *up = false;
+            }
+        });
+    }
+
     pub fn repair<A: ToIpAddr>(&mut self, group: &[A]) {
-        for a in group.iter().map(|a| a.to_ip_addr().unwrap()) {
-            for b in group.iter().map(|a| a.to_ip_addr().unwrap()) {
-                self.links.insert((a, b));
+        let group = group
+            .iter()
+            .map(|a| a.to_ip_addr().unwrap())
+            .collect::<BTreeSet<_>>();
+        self.blocked
+            .retain(|(from, to)| !group.contains(from) || !group.contains(to));
+        self.for_each_link(|from, to, up| {
+            if group.contains(&from) && group.contains(&to) {
+                *up = true;
             }
+        });
+    }
+
+    /// Drops packets from `from` to `to` on any route.
+    pub fn block(&mut self, from: IpAddr, to: IpAddr) {
+        self.expect_vertex(from);
+        self.expect_vertex(to);
+        self.blocked.insert((from, to));
+    }
+
+    pub fn unblock(&mut self, from: IpAddr, to: IpAddr) {
+        self.blocked.remove(&(from, to));
+    }
+
+    pub fn set_link_up(&mut self, from: IpAddr, to: IpAddr, up: bool) {
+        if let Some(link) = self.links.get_mut(&from).and_then(|l| l.get_mut(&to)) {
+            *link = up;
         }
     }
 
     pub fn repair_all(&mut self) {
-        for a in self.nodes.iter() {
-            for b in self.nodes.iter() {
-                self.links.insert((*a, *b));
+        self.blocked.clear();
+        self.for_each_link(|_, _, up| *up = true);
+    }
+
+    /// Each group becomes an island, unlisted nodes form one more island.
+    /// Links between islands are down. Unlisted routers are shared:
+    /// their links stay up, but nodes of different islands are blocked
+    /// until they are repaired.
+    pub fn partition<A: ToIpAddr>(&mut self, groups: &[&[A]]) {
+        let island = self.islands(groups);
+        let shared = |addr: &IpAddr| self.routers.contains(addr) && !island.contains_key(addr);
+        let links = self
+            .links
+            .iter()
+            .flat_map(|(from, links)| links.keys().map(move |to| (*from, *to)))
+            .map(|(from, to)| {
+                let up = shared(&from) || shared(&to) || island.get(&from) == island.get(&to);
+                (from, to, up)
+            })
+            .collect::<Vec<_>>();
+        for (from, to, up) in links {
+            self.set_link_up(from, to, up);
+        }
+        self.blocked.clear();
+        for from in &self.nodes {
+            for to in &self.nodes {
+                if island.get(from) != island.get(to) {
+                    self.blocked.insert((*from, *to));
+                }
             }
         }
     }
 
+    /// Whether packets can be delivered in both directions.
+    pub fn is_connected(&self, a: IpAddr, b: IpAddr) -> bool {
+        self.hops(a, b).is_some() && self.hops(b, a).is_some()
+    }
+
+    /// Groups of the registered nodes,
+    /// in which nodes are adjacent if they are connected in both directions.
+    pub fn components(&self) -> Vec<Vec<IpAddr>> {
+        let mut unvisited = self.nodes.clone();
+        let mut components = Vec::new();
+        while let Some(start) = unvisited.pop_first() {
+            let mut component = vec![start];
+            let mut index = 0;
+            while index < component.len() {
+                let node = component[index];
+                let adjacent = unvisited
+                    .iter()
+                    .copied()
+                    .filter(|other| self.is_connected(node, *other))
+                    .collect::<Vec<_>>();
+                for other in adjacent {
+                    unvisited.remove(&other);
+                    component.push(other);
+                }
+                index += 1;
+            }
+            component.sort();
+            components.push(component);
+        }
+        components
+    }
+
     pub fn node_registered(&self, addr: impl ToIpAddr) -> bool {
         self.nodes.contains(&addr.to_ip_addr().unwrap())
     }
 
     pub fn hops(&self, from: impl ToIpAddr, to: impl ToIpAddr) -> Option<usize> {
-        let from = from.to_ip_addr().unwrap();
-        let to = to.to_ip_addr().unwrap();
+        self.route(from.to_ip_addr().unwrap(), to.to_ip_addr().unwrap())
+            .map(|route| route.len())
+    }
+
+    /// Links of the shortest path between the nodes,
+    /// empty if the nodes are equal.
+    pub fn route(&self, from: IpAddr, to: IpAddr) -> Option<Vec<(IpAddr, IpAddr)>> {
         if !self.node_registered(from) || !self.node_registered(to) {
-            None
-        } else if from == to {
-            Some(0)
-        } else if self.links.contains(&(from, to)) {
-            Some(1)
-        } else {
-            None
+            return None;
+        }
+        if from == to {
+            return Some(Vec::new());
+        }
+        if self.blocked.contains(&(from, to)) {
+            return None;
+        }
+        // breadth-first search, only routers are expanded
+        let mut prev = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(vertex) = queue.pop_front() {
+            if vertex != from && !self.routers.contains(&vertex) {
+                continue;
+            }
+            for (next, up) in self.links.get(&vertex).into_iter().flatten() {
+                if !*up || prev.contains_key(next) {
+                    continue;
+                }
+                prev.insert(*next, vertex);
+                if *next == to {
+                    let mut route = Vec::new();
+                    let mut current = to;
+                    while current != from {
+                        let previous = prev[&current];
+                        route.push((previous, current));
+                        current = previous;
+                    }
+                    route.reverse();
+                    return Some(route);
+                }
+                queue.push_back(*next);
+            }
+        }
+        None
+    }
+
+    pub fn set_link_config(&mut self, from: IpAddr, to: IpAddr, config: LinkConfig) {
+        self.configs.insert((from, to), config);
+    }
+
+    /// Properties of the directed link, they are kept while the link is down.
+    pub fn link_config(&self, from: IpAddr, to: IpAddr) -> LinkConfig {
+        self.configs.get(&(from, to)).copied().unwrap_or_default()
+    }
+
+    /// Island of each listed vertex, panics if a vertex is unknown
+    /// or listed in several groups.
+    pub fn islands<A: ToIpAddr>(&self, groups: &[&[A]]) -> HashMap<IpAddr, usize> {
+        let mut island = HashMap::new();
+        for (index, group) in groups.iter().enumerate() {
+            for addr in group.iter().map(|a| a.to_ip_addr().unwrap()) {
+                self.expect_vertex(addr);
+                if island.insert(addr, index).is_some() {
+                    panic!("node '{}' is listed in several groups", addr);
+                }
+            }
+        }
+        island
+    }
+
+    pub fn expect_vertex(&self, addr: IpAddr) -> IpAddr {
+        if !self.nodes.contains(&addr) && !self.routers.contains(&addr) {
+            panic!("node '{}' is not registered", addr);
+        }
+        addr
+    }
+
+    fn add_link(&mut self, from: IpAddr, to: IpAddr) {
+        *self.links.entry(from).or_default().entry(to).or_default() = true;
+    }
+
+    fn remove_link(&mut self, from: IpAddr, to: IpAddr) {
+        if let Some(links) = self.links.get_mut(&from) {
+            links.remove(&to);
+        }
+    }
+
+    fn for_each_link(&mut self, mut f: impl FnMut(IpAddr, IpAddr, &mut bool)) {
+        for (from, links) in self.links.iter_mut() {
+            for (to, up) in links.iter_mut() {
+                f(*from, *to, up);
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::IpAddr;
+
     use super::NetworkTopology;
 
     #[test]
@@ -144,4 +377,174 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn one_way_links() {
+        let mut topology = NetworkTopology::new();
+        let first = "192.168.1.2".parse().unwrap();
+        let second = "192.168.1.3".parse().unwrap();
+        topology.register_node(first);
+        topology.register_node(second);
+
+        topology.set_link_up(first, second, false);
+        assert_eq!(topology.hops(first, second), None);
+        assert_eq!(topology.hops(second, first), Some(1));
+        topology.set_link_up(first, first, false);
+        assert_eq!(topology.hops(first, first), Some(0));
+
+        topology.set_link_up(first, second, true);
+        assert_eq!(topology.hops(first, second), Some(1));
+    }
+
+    #[test]
+    fn blocked_route() {
+        let mut topology = NetworkTopology::new();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let (a, b, switch) = (ip("10.0.0.1"), ip("10.0.0.2"), ip("10.0.1.1"));
+        topology.set_full_mesh(false);
+        topology.add_router(switch);
+        for node in [a, b] {
+            topology.register_node(node);
+            topology.connect(node, switch);
+        }
+
+        // there is no direct link, the route through the switch is blocked
+        topology.block(a, b);
+        assert_eq!(topology.hops(a, b), None);
+        assert_eq!(topology.hops(b, a), Some(2));
+        topology.unblock(a, b);
+        assert_eq!(topology.hops(a, b), Some(2));
+
+        topology.block(b, a);
+        topology.repair(&[a, b]);
+        assert_eq!(topology.hops(b, a), Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "node '10.0.0.3' is not registered")]
+    fn block_unknown() {
+        let mut topology = NetworkTopology::new();
+        let a = "10.0.0.1".parse().unwrap();
+        topology.register_node(a);
+        topology.block(a, "10.0.0.3".parse().unwrap());
+    }
+
+    #[test]
+    fn partition() {
+        let mut topology = NetworkTopology::new();
+        let ips = (1..=5)
+            .map(|i| format!("10.0.0.{}", i).parse::<IpAddr>().unwrap())
+            .collect::<Vec<_>>();
+        for ip in ips.iter() {
+            topology.register_node(*ip);
+        }
+        assert_eq!(topology.components(), vec![ips.clone()]);
+
+        topology.partition(&[&ips[..2], &ips[2..3]]);
+        assert!(topology.is_connected(ips[0], ips[1]));
+        assert!(!topology.is_connected(ips[1], ips[2]));
+        assert!(topology.is_connected(ips[3], ips[4]));
+        assert_eq!(
+            topology.components(),
+            vec![ips[..2].to_vec(), ips[2..3].to_vec(), ips[3..].to_vec()]
+        );
+
+        // one-way link does not connect nodes
+        topology.set_link_up(ips[0], ips[2], true);
+        assert!(!topology.is_connected(ips[0], ips[2]));
+        assert_eq!(topology.components().len(), 3);
+
+        topology.repair_all();
+        assert_eq!(topology.components(), vec![ips]);
+    }
+
+    #[test]
+    fn partition_behind_router() {
+        let mut topology = NetworkTopology::new();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let (a, b, c) = (ip("10.0.0.1"), ip("10.0.0.2"), ip("10.0.0.3"));
+        let switch = ip("10.0.1.1");
+        topology.set_full_mesh(false);
+        topology.add_router(switch);
+        for node in [a, b, c] {
+            topology.register_node(node);
+            topology.connect(node, switch);
+        }
+
+        topology.partition(&[&[a, c], &[b]]);
+        assert_eq!(topology.hops(a, c), Some(2));
+        assert_eq!(topology.hops(a, b), None);
+        assert_eq!(topology.hops(b, c), None);
+        assert_eq!(topology.components(), vec![vec![a, c], vec![b]]);
+
+        // repaired nodes are connected through the shared router
+        topology.repair(&[a, b]);
+        assert_eq!(topology.hops(a, b), Some(2));
+        assert_eq!(topology.hops(b, c), None);
+        assert_eq!(topology.components(), vec![vec![a, b, c]]);
+        topology.separate(&[b]);
+        assert_eq!(topology.components(), vec![vec![a, c], vec![b]]);
+
+        // listed router belongs to the island
+        topology.partition(&[&[a, switch], &[b, c]]);
+        assert_eq!(topology.components(), vec![vec![a], vec![b], vec![c]]);
+
+        topology.repair_all();
+        assert_eq!(topology.components(), vec![vec![a, b, c]]);
+    }
+
+    #[test]
+    fn routing() {
+        let mut topology = NetworkTopology::new();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let (a, b, c) = (ip("10.0.0.1"), ip("10.0.0.2"), ip("10.0.0.3"));
+        let (switch1, switch2) = (ip("10.0.1.1"), ip("10.0.1.2"));
+        topology.set_full_mesh(false);
+        for node in [a, b, c] {
+            topology.register_node(node);
+        }
+        topology.add_router(switch1);
+        topology.add_router(switch2);
+        // a - switch1 - switch2 - b
+        //        \
+        //         c
+        topology.connect(a, switch1);
+        topology.connect(switch1, switch2);
+        topology.connect(switch2, b);
+        topology.connect(switch1, c);
+
+        assert_eq!(
+            topology.route(a, b),
+            Some(vec![(a, switch1), (switch1, switch2), (switch2, b)])
+        );
+        assert_eq!(topology.hops(a, c), Some(2));
+        assert_eq!(topology.hops(b, c), Some(3));
+        assert_eq!(topology.hops(switch1, a), None);
+
+        // shortcut between nodes, but nodes do not forward packets
+        topology.connect(b, c);
+        assert_eq!(topology.hops(b, c), Some(1));
+        assert_eq!(topology.hops(a, b), Some(3));
+
+        // failed switch partitions nodes behind it
+        topology.separate(&[switch1]);
+        assert_eq!(topology.components(), vec![vec![a], vec![b, c]]);
+        topology.repair_all();
+        assert_eq!(topology.components(), vec![vec![a, b, c]]);
+
+        topology.set_link_up(switch2, b, false);
+        assert_eq!(topology.hops(a, b), None);
+        assert_eq!(topology.hops(b, a), Some(3));
+
+        topology.set_full_mesh(true);
+        assert_eq!(topology.hops(a, b), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "address '10.0.0.1' is used by a router")]
+    fn router_address() {
+        let mut topology = NetworkTopology::new();
+        topology.add_router("10.0.0.1".parse().unwrap());
+        topology.register_node("10.0.0.1");
+    }
 }
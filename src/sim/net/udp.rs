@@ -24,6 +24,8 @@ pub struct UpdSocketData {
 pub struct UdpSocket {
     data: Rc<RefCell<UpdSocketData>>,
     owner_node: NodeHandle,
+    // socket is closed if the node crashed after the bind
+    incarnation: u64,
 }
 
 impl UdpSocket {
@@ -54,6 +56,7 @@ impl UdpSocket {
                 if net.register_upd_socket(socket.clone()).is_ok() {
                     return Ok(Self {
                         data: socket,
+                        incarnation: node.incarnation(),
                         owner_node: node,
                     });
                 }
@@ -80,6 +83,12 @@ impl UdpSocket {
         if target.ip().is_loopback() {
             target.set_ip(self.owner_node.ip());
         }
+        if !self.is_open() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "socket has been closed by the node crash",
+            ));
+        }
         let node = self.owner_node.clone();
         let info = node.info();
         let buf = &buf[..info.udp_send_buffer_size.min(buf.len())];
@@ -108,12 +117,17 @@ impl UdpSocket {
     pub fn local_addr(&self) -> SocketAddr {
         self.data.borrow().local_addr
     }
+
+    fn is_open(&self) -> bool {
+        self.owner_node.alive() && self.owner_node.incarnation() == self.incarnation
+    }
 }
 
 impl Drop for UdpSocket {
     fn drop(&mut self) {
         // udp socket can be dropped outside of sim
-        if self.owner_node.alive() {
+        // or after the owner node crashed
+        if self.is_open() {
             self.owner_node.return_port(self.local_addr().port());
             if self.owner_node.network_handle().alive() {
                 self.owner_node
@@ -174,7 +188,7 @@ mod tests {
         node1.make_steps(None);
         node2.make_steps(None);
         node1.make_steps(None);
-        assert_eq!(flag.load(std::sync::atomic::Ordering::SeqCst), true);
+        assert!(flag.load(std::sync::atomic::Ordering::SeqCst));
     }
 
     #[test_case("10.12.1.1:80", "10.12.1.1:80")]
@@ -202,7 +216,7 @@ mod tests {
             }
         });
         sim.make_steps();
-        assert_eq!(flag.load(std::sync::atomic::Ordering::SeqCst), true);
+        assert!(flag.load(std::sync::atomic::Ordering::SeqCst));
         assert_eq!(node.time(), Timestamp::from_secs(0));
     }
 
@@ -3,22 +3,28 @@ mod info;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use std::{
     collections::BTreeSet,
     future::Future,
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr},
+    panic::Location,
+    pin::Pin,
     rc::{Rc, Weak},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use crate::{sim::runtime::JoinHandle, time::Timestamp};
+use crate::{
+    sim::runtime::{JoinHandle, TaskInfo},
+    time::Timestamp,
+};
 
 use super::{
     context::ContextGuard,
     net::NetworkHandle,
-    runtime::Runtime,
-    time::{TimeDriver, TimerEntry},
+    report::NodeReport,
+    runtime::{PanicContext, PanicOrigin, PanicPolicy, Runtime, TaskPanic},
+    time::{Clock, TimeDriver, TimerEntry},
 };
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -28,24 +34,46 @@ use info::NodeInfo;
 
 ////////////////////////////////////////////////////////////////////////////////
 
+type NodeMain = Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()>>>>;
+
 struct NodeState {
     runtime: Runtime,
     time_driver: TimeDriver,
     network_handle: NetworkHandle,
     info: NodeInfo,
     free_ports: RefCell<BTreeSet<u16>>,
+    main: Option<(NodeMain, &'static Location<'static>)>,
+    // incremented on every crash
+    incarnation: Cell<u64>,
+    paused: Cell<bool>,
+    // panic which crashed the node with the `CrashNode` policy
+    last_panic: RefCell<Option<PanicContext>>,
 }
 
 impl NodeState {
-    fn new(info: NodeInfo, network_handle: NetworkHandle) -> Self {
+    fn new(info: NodeInfo, network_handle: NetworkHandle, clock: Clock, seed: u64) -> Self {
+        let runtime = Runtime::new();
+        runtime.set_panic_origin(PanicOrigin {
+            ip: info.ip,
+            seed,
+            clock: clock.clone(),
+        });
         Self {
-            runtime: Runtime::new(),
-            time_driver: TimeDriver::new(),
+            runtime,
+            time_driver: TimeDriver::new(clock),
             network_handle,
             info,
-            free_ports: RefCell::new(BTreeSet::from_iter(1..=u16::MAX)),
+            free_ports: RefCell::new(Self::all_ports()),
+            main: None,
+            incarnation: Cell::new(0),
+            paused: Cell::new(false),
+            last_panic: RefCell::new(None),
         }
     }
+
+    fn all_ports() -> BTreeSet<u16> {
+        BTreeSet::from_iter(1..=u16::MAX)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -59,6 +87,17 @@ impl Node {
     pub fn handle(&self) -> NodeHandle {
         NodeHandle(Rc::downgrade(&self.0))
     }
+
+    /// Node which runs the future passed to [`Sim::block_on`](super::Sim::block_on).
+    /// It is not registered in the simulation and the network.
+    pub(crate) fn controller(network_handle: NetworkHandle, clock: Clock, seed: u64) -> Self {
+        let info = NodeInfo {
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            udp_send_buffer_size: Self::UDP_SEND_BUF_SIZE,
+            udp_recv_buffer_size: Self::UDP_RECV_BUF_SIZE,
+        };
+        Self(Rc::new(NodeState::new(info, network_handle, clock, seed)))
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -74,7 +113,7 @@ pub struct NodeHandle(Weak<NodeState>);
 
 impl NodeHandle {
     pub fn current() -> Self {
-        Self::get_current().unwrap()
+        Self::get_current().expect("called outside of a simulation node")
     }
 
     pub(crate) fn exists() -> bool {
@@ -91,24 +130,96 @@ impl NodeHandle {
 
     ////////////////////////////////////////////////////////////////////////////////
 
+    #[track_caller]
     pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
     where
         F: Future + 'static,
     {
-        self.state().runtime.spawn(task)
+        self.spawn_at(task, None, Location::caller())
+    }
+
+    pub(crate) fn spawn_at<F>(
+        &self,
+        task: F,
+        name: Option<String>,
+        location: &'static Location<'static>,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.state().runtime.spawn_at(task, name, location)
+    }
+
+    /// Snapshot of the unfinished node tasks.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.state().runtime.tasks()
+    }
+
+    pub(crate) fn report(&self) -> NodeReport {
+        NodeReport {
+            ip: self.ip(),
+            tasks: self.tasks(),
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////////
 
-    pub fn next_step(&self) -> bool {
-        let _guard = ContextGuard::new(self.clone());
+    /// Crashes the node: all its tasks are dropped, timers are cancelled,
+    /// sockets are closed and ports are released.
+    pub fn crash(&self) {
         let state = self.state();
-        let runtime_made_step = state.runtime.next_step();
-        if runtime_made_step {
+        state.runtime.clear();
+        state.time_driver.clear();
+        state.incarnation.set(state.incarnation.get() + 1);
+        state.network_handle.deregister_node_sockets(state.info.ip);
+        *state.free_ports.borrow_mut() = NodeState::all_ports();
+        state.paused.set(false);
+    }
+
+    /// Context of the last panic which crashed the node
+    /// with [`PanicPolicy::CrashNode`].
+    pub fn last_panic(&self) -> Option<PanicContext> {
+        self.state().last_panic.borrow().clone()
+    }
+
+    /// Crashes the node and runs its main function
+    /// set with [`NodeBuilder::main`], if any.
+    pub fn restart(&self) {
+        self.crash();
+        self.run_main();
+    }
+
+    /// Freezes the node: its tasks are not polled and its timers do not fire
+    /// until [`NodeHandle::resume`] is called. Incoming datagrams are still
+    /// delivered to the receive buffers of the node sockets.
+    pub fn pause(&self) {
+        self.state().paused.set(true);
+    }
+
+    /// Resumes the paused node. Timers which expired during the pause
+    /// fire at the current time.
+    pub fn resume(&self) {
+        self.state().paused.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state().paused.get()
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Makes one step of the node: polls one of its tasks, or,
+    /// if there are no runnable tasks, advances the global time
+    /// to the next timer of the node or to the next network event.
+    ///
+    /// Use [`Sim::make_steps`](super::Sim::make_steps) to step all nodes
+    /// in the order of the global time.
+    pub fn next_step(&self) -> bool {
+        if self.poll_task() {
             true
         } else if let Some(time) = self.next_event_timestamp() {
-            state.network_handle.advance_to_time(time);
-            state.time_driver.advance_to_time(time);
+            self.state().network_handle.advance_to_time(time);
+            self.fire_timers(time);
             true
         } else {
             false
@@ -133,7 +244,7 @@ impl NodeHandle {
     }
 
     pub fn step_duration(&self, duration: Duration) -> usize {
-        let until = self.time() + duration;
+        let until = self.global_time() + duration;
         let mut steps = 0;
         while let Some(next) = self.next_event_timestamp() {
             if next <= until {
@@ -143,8 +254,9 @@ impl NodeHandle {
                 break;
             }
         }
-        assert!(self.time() <= until);
-        self.state().time_driver.advance_to_time(until);
+        assert!(self.global_time() <= until);
+        self.state().network_handle.advance_to_time(until);
+        self.fire_timers(until);
         steps
     }
 
@@ -154,10 +266,30 @@ impl NodeHandle {
         self.state().info.ip
     }
 
+    /// Local time of the node, which can be skewed
+    /// relative to the global time of the simulation.
     pub fn time(&self) -> Timestamp {
         self.state().time_driver.time()
     }
 
+    pub fn system_time(&self) -> SystemTime {
+        self.state().time_driver.system_time()
+    }
+
+    /// Steps the wall-clock time of the node, like NTP correction
+    /// or manual change of the system clock. Local time is not changed.
+    pub fn set_system_time(&self, time: SystemTime) {
+        self.state().time_driver.set_system_time(time);
+    }
+
+    pub fn jump_clock(&self, delta: Duration) {
+        self.state().time_driver.jump_forward(delta);
+    }
+
+    pub fn jump_clock_back(&self, delta: Duration) {
+        self.state().time_driver.jump_backward(delta);
+    }
+
     ////////////////////////////////////////////////////////////////////////////////
 
     pub(crate) fn info(&self) -> NodeInfo {
@@ -177,37 +309,106 @@ impl NodeHandle {
         assert!(not_existed);
     }
 
+    pub(crate) fn incarnation(&self) -> u64 {
+        self.state().incarnation.get()
+    }
+
+    pub(crate) fn run_main(&self) {
+        let state = self.state();
+        if let Some((main, location)) = &state.main {
+            state.runtime.spawn_at(main(), None, location);
+        }
+    }
+
     pub(crate) fn get_current() -> Option<NodeHandle> {
         NODE_HANDLE.with(|h| h.borrow().as_ref().cloned())
     }
 
     ////////////////////////////////////////////////////////////////////////////////
 
-    pub(crate) fn add_timer(&self, duration: Duration) -> Rc<TimerEntry> {
+    pub(crate) fn add_timer(&self, timestamp: Timestamp) -> Rc<TimerEntry> {
+        self.state().time_driver.add_timer(timestamp)
+    }
+
+    pub(crate) fn remove_timer(&self, entry: &TimerEntry) {
+        self.state().time_driver.remove_timer(entry);
+    }
+
+    pub(crate) fn has_work(&self) -> bool {
+        !self.is_paused() && self.state().runtime.has_work()
+    }
+
+    pub(crate) fn poll_task(&self) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        let stepped = {
+            let _guard = ContextGuard::new(self.clone());
+            self.state().runtime.next_step()
+        };
+        let panic = self.state().runtime.take_panic();
+        if let Some(panic) = panic {
+            self.handle_panic(panic);
+        }
+        stepped
+    }
+
+    pub(crate) fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.state().runtime.set_panic_policy(policy);
+    }
+
+    fn handle_panic(&self, panic: TaskPanic) {
+        match self.state().runtime.panic_policy() {
+            PanicPolicy::Propagate => panic!("{}", panic.context),
+            PanicPolicy::CrashNode => {
+                *self.state().last_panic.borrow_mut() = Some(panic.context);
+                self.crash();
+            }
+            PanicPolicy::Capture => unreachable!("captured panics are passed to join handles"),
+        }
+    }
+
+    pub(crate) fn next_timer_timestamp(&self) -> Option<Timestamp> {
+        if self.is_paused() {
+            return None;
+        }
+        self.state().time_driver.next_timer_global_time()
+    }
+
+    /// Fires timers of the node which expire not later than `time`.
+    pub(crate) fn fire_timers(&self, time: Timestamp) {
         let state = self.state();
-        let time = state.time_driver.time();
-        state.time_driver.add_timer(time + duration)
+        if state.paused.get() {
+            state
+                .time_driver
+                .clock()
+                .advance_to_time(time.max(self.global_time()));
+        } else {
+            state.time_driver.advance_to_time(time);
+        }
     }
 
     fn next_event_timestamp(&self) -> Option<Timestamp> {
         let state = self.state();
-        if state.runtime.has_work() {
-            Some(self.time())
+        if self.has_work() {
+            Some(self.global_time())
         } else {
-            let next_time_driver = state.time_driver.next_timer().map(|entry| entry.timestamp);
+            let next_timer = self.next_timer_timestamp();
             let next_network = state.network_handle.next_event_timestamp();
-            if next_time_driver.is_none() {
-                next_network
-            } else if next_network.is_none() {
-                next_time_driver
-            } else {
-                let time_driver = next_time_driver.unwrap();
-                let network = next_network.unwrap();
-                Some(time_driver.min(network))
-            }
+            // timers can be overdue if the global time
+            // was advanced while stepping other nodes
+            next_timer
+                .into_iter()
+                .chain(next_network)
+                .min()
+                .map(|time| time.max(self.global_time()))
         }
     }
 
+    fn global_time(&self) -> Timestamp {
+        self.state().time_driver.clock().time()
+    }
+
     ////////////////////////////////////////////////////////////////////////////////
 
     pub(crate) fn alive(&self) -> bool {
@@ -221,11 +422,11 @@ impl NodeHandle {
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::BTreeSet, net::IpAddr};
+    use std::{cell::RefCell, collections::BTreeSet, net::IpAddr, rc::Rc, time::Duration};
 
-    use crate::sim::Sim;
+    use crate::sim::{sleep, spawn, task::Builder, PanicPolicy, Sim, UdpSocket};
 
-    use super::{info::NodeInfo, NodeState};
+    use super::{info::NodeInfo, NodeBuilder, NodeState};
 
     #[test]
     fn free_ports() {
@@ -237,6 +438,8 @@ mod tests {
                 udp_recv_buffer_size: 0,
             },
             sim.network(),
+            sim.clock(),
+            sim.seed(),
         );
         assert_eq!(node_state.free_ports.borrow().len(), u16::MAX.into());
         assert_eq!(
@@ -244,4 +447,239 @@ mod tests {
             BTreeSet::from_iter(1..=u16::MAX)
         );
     }
+
+    #[test]
+    fn crash_and_restart() {
+        let mut sim = Sim::new(123);
+        let starts = Rc::new(RefCell::new(0));
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let server = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .main({
+                let starts = starts.clone();
+                let received = received.clone();
+                move || {
+                    *starts.borrow_mut() += 1;
+                    let received = received.clone();
+                    async move {
+                        let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+                        loop {
+                            let mut buf = [0u8; 1];
+                            socket.recv_from(&mut buf).await;
+                            received.borrow_mut().push(buf[0]);
+                        }
+                    }
+                }
+            })
+            .build(&mut sim)
+            .unwrap();
+        let client = NodeBuilder::with_ip("10.0.0.2")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        client.spawn(async {
+            let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+            for i in 0..3 {
+                socket.send_to(&[i], "10.0.0.1:80").unwrap();
+                sleep(Duration::from_secs(1)).await;
+            }
+        });
+        sim.run_for(Duration::from_millis(1600));
+        assert_eq!(*received.borrow(), vec![0, 1]);
+
+        sim.crash(server.ip());
+        sim.run_for(Duration::from_secs(1));
+        assert_eq!(*received.borrow(), vec![0, 1]);
+        assert_eq!(*starts.borrow(), 1);
+
+        server.restart();
+        let timer_fired = Rc::new(RefCell::new(false));
+        server.spawn({
+            let timer_fired = timer_fired.clone();
+            async move {
+                sleep(Duration::from_secs(10)).await;
+                *timer_fired.borrow_mut() = true;
+            }
+        });
+        server.crash();
+        server.restart();
+        sim.make_steps();
+        assert_eq!(*starts.borrow(), 3);
+        assert!(!*timer_fired.borrow());
+        assert_eq!(
+            server.state().free_ports.borrow().len(),
+            u16::MAX as usize - 1
+        );
+    }
+
+    #[test]
+    fn socket_outlives_crash() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let (send, recv) = std::sync::mpsc::channel();
+        node.spawn(async move {
+            send.send(UdpSocket::bind("0.0.0.0:80").unwrap()).unwrap();
+        });
+        sim.make_steps();
+        let socket = recv.recv().unwrap();
+        node.crash();
+        assert!(socket.send_to(b"hello", "10.0.0.1:80").is_err());
+        node.spawn(async {
+            UdpSocket::bind("0.0.0.0:80").unwrap();
+        });
+        sim.make_steps();
+        drop(socket);
+        assert_eq!(node.state().free_ports.borrow().len(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut sim = Sim::new(123);
+        let ticks = Rc::new(RefCell::new(Vec::new()));
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .udp_recv_buffer_size(2)
+            .build(&mut sim)
+            .unwrap();
+        let sender = NodeBuilder::with_ip("10.0.0.2")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        node.spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    sleep(Duration::from_secs(1)).await;
+                    ticks.borrow_mut().push(crate::sim::now());
+                }
+            }
+        });
+        node.spawn({
+            let received = received.clone();
+            async move {
+                let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+                loop {
+                    let mut buf = [0u8; 1];
+                    socket.recv_from(&mut buf).await;
+                    received.borrow_mut().push(buf[0]);
+                }
+            }
+        });
+        sim.run_for(Duration::from_millis(2500));
+        node.pause();
+        sender.spawn(async {
+            let socket = UdpSocket::bind("0.0.0.0:80").unwrap();
+            for i in 0..3 {
+                socket.send_to(&[i], "10.0.0.1:80").unwrap();
+            }
+        });
+        sim.run_for(Duration::from_secs(3));
+        assert!(node.is_paused());
+        assert_eq!(
+            *ticks.borrow(),
+            vec![Duration::from_secs(1), Duration::from_secs(2)]
+        );
+        assert!(received.borrow().is_empty());
+
+        node.resume();
+        sim.run_for(Duration::from_millis(100));
+        assert_eq!(ticks.borrow().last(), Some(&Duration::from_millis(5500)));
+        // receive buffer overflowed during the pause
+        assert_eq!(received.borrow().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "'worker' (Running, polled 2 times) spawned at src/sim/node.rs")]
+    fn panic_propagated() {
+        let mut sim = Sim::new(321);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        Builder::new().name("worker").spawn_on(
+            async {
+                sleep(Duration::from_secs(1)).await;
+                panic!("boom");
+            },
+            &node,
+        );
+        sim.run_for(Duration::from_secs(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "panicked on node 10.0.0.1 at 1s (seed 321): boom")]
+    fn panic_context() {
+        let mut sim = Sim::new(321);
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        node.spawn(async {
+            sleep(Duration::from_secs(1)).await;
+            panic!("boom");
+        });
+        sim.run_for(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn panic_crashes_node() {
+        let mut sim = Sim::new(123);
+        sim.set_panic_policy(PanicPolicy::CrashNode);
+        let starts = Rc::new(RefCell::new(0));
+        let node = NodeBuilder::with_ip("10.0.0.1")
+            .unwrap()
+            .main({
+                let starts = starts.clone();
+                move || {
+                    *starts.borrow_mut() += 1;
+                    async {
+                        sleep(Duration::from_secs(1)).await;
+                        panic!("boom");
+                    }
+                }
+            })
+            .build(&mut sim)
+            .unwrap();
+        let other = NodeBuilder::with_ip("10.0.0.2")
+            .unwrap()
+            .panic_policy(PanicPolicy::Capture)
+            .build(&mut sim)
+            .unwrap();
+        let captured = other.spawn(async {
+            let handle = spawn(async {
+                sleep(Duration::from_secs(2)).await;
+                panic!("captured boom");
+            });
+            handle.await.unwrap_err()
+        });
+        node.spawn(async { sleep(Duration::from_secs(10)).await });
+        sim.run_for(Duration::from_secs(5));
+        assert!(node.tasks().is_empty());
+        assert_eq!(*starts.borrow(), 1);
+
+        let context = node.last_panic().unwrap();
+        assert_eq!(context.ip, node.ip());
+        assert_eq!(context.time, Duration::from_secs(1));
+        assert_eq!(context.seed, 123);
+        assert_eq!(context.message, "boom");
+        assert_eq!(context.task.unwrap().location.file(), file!());
+        assert!(other.last_panic().is_none());
+
+        let error = sim.block_on(captured).unwrap();
+        assert!(error.is_panic());
+        let context = error.panic_context().unwrap();
+        assert_eq!(context.ip, other.ip());
+        assert_eq!(context.time, Duration::from_secs(2));
+        assert_eq!(context.message, "captured boom");
+        assert!(error
+            .to_string()
+            .ends_with("panicked on node 10.0.0.2 at 2s (seed 123): captured boom"));
+
+        node.restart();
+        assert_eq!(*starts.borrow(), 2);
+    }
 }
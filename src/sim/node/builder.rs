@@ -1,15 +1,26 @@
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::{io, net::IpAddr, rc::Rc};
+use std::{future::Future, io, net::IpAddr, panic::Location, rc::Rc, time::Duration};
 
-use crate::{net::ip_addr::ToIpAddr, sim::Sim};
+use crate::{
+    net::ip_addr::ToIpAddr,
+    sim::{
+        runtime::{PanicPolicy, Scheduler, SchedulingPolicy},
+        Sim,
+    },
+};
 
-use super::{info::NodeInfo, Node, NodeHandle, NodeState};
+use super::{info::NodeInfo, Node, NodeHandle, NodeMain, NodeState};
 
 pub struct NodeBuilder {
     ip: IpAddr,
     udp_send_buffer_size: usize,
     udp_recv_buffer_size: usize,
+    main: Option<(NodeMain, &'static Location<'static>)>,
+    clock_offset: Duration,
+    clock_drift: i32,
+    scheduling_policy: Option<SchedulingPolicy>,
+    panic_policy: Option<PanicPolicy>,
 }
 
 impl NodeBuilder {
@@ -25,22 +36,81 @@ impl NodeBuilder {
                     ip,
                     udp_send_buffer_size: Node::UDP_SEND_BUF_SIZE,
                     udp_recv_buffer_size: Node::UDP_RECV_BUF_SIZE,
+                    main: None,
+                    clock_offset: Duration::ZERO,
+                    clock_drift: 0,
+                    scheduling_policy: None,
+                    panic_policy: None,
                 })
             }
         })
     }
 
     pub fn build(self, sim: &mut Sim) -> Option<NodeHandle> {
-        let node = Node(Rc::new(NodeState::new(
+        let mut state = NodeState::new(
             NodeInfo {
                 ip: self.ip,
                 udp_send_buffer_size: self.udp_send_buffer_size,
                 udp_recv_buffer_size: self.udp_recv_buffer_size,
             },
             sim.network(),
-        )));
+            sim.clock(),
+            sim.seed(),
+        );
+        state.main = self.main;
+        state.time_driver.jump_forward(self.clock_offset);
+        state.time_driver.set_drift(self.clock_drift);
+        let policy = self
+            .scheduling_policy
+            .unwrap_or_else(|| sim.scheduling_policy());
+        state
+            .runtime
+            .set_scheduler(Scheduler::new(policy, sim.node_seed(self.ip)));
+        state
+            .runtime
+            .set_panic_policy(self.panic_policy.unwrap_or_else(|| sim.panic_policy()));
 
-        sim.add_node(node)
+        let handle = sim.add_node(Node(Rc::new(state)))?;
+        handle.run_main();
+        Some(handle)
+    }
+
+    /// Sets the entry point of the node.
+    /// It is spawned when the node is built and on every restart.
+    #[track_caller]
+    pub fn main<F, Fut>(mut self, main: F) -> Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.main = Some((Box::new(move || Box::pin(main())), Location::caller()));
+        self
+    }
+
+    /// Local clock of the node will be ahead of the global time by `offset`.
+    pub fn clock_offset(mut self, offset: Duration) -> Self {
+        self.clock_offset = offset;
+        self
+    }
+
+    /// Local clock of the node will run faster (or slower, if `ppm` is negative)
+    /// than the global time by `ppm` microseconds per second.
+    pub fn clock_drift(mut self, ppm: i32) -> Self {
+        self.clock_drift = ppm;
+        self
+    }
+
+    /// Overrides the scheduling policy
+    /// set with [`Sim::set_scheduling_policy`].
+    pub fn scheduling_policy(mut self, policy: SchedulingPolicy) -> Self {
+        self.scheduling_policy = Some(policy);
+        self
+    }
+
+    /// Overrides the panic policy set with [`Sim::set_panic_policy`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = Some(policy);
+        self
     }
 
     pub fn udp_send_buffer_size(mut self, size: usize) -> Self {
@@ -56,7 +126,9 @@ impl NodeBuilder {
 
 #[cfg(test)]
 mod tests {
-    use crate::sim::Sim;
+    use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+    use crate::sim::{SchedulingPolicy, Sim};
 
     use super::NodeBuilder;
 
@@ -75,4 +147,33 @@ mod tests {
         NodeBuilder::with_ip(ip).unwrap().build(&mut sim).unwrap();
         assert!(NodeBuilder::with_ip(ip).unwrap().build(&mut sim).is_none());
     }
+
+    #[test]
+    fn scheduling_policy() {
+        let order = |seed: u64, policy: Option<SchedulingPolicy>| {
+            let mut sim = Sim::new(seed);
+            sim.set_scheduling_policy(SchedulingPolicy::Random);
+            let mut builder = NodeBuilder::with_ip("10.0.0.1").unwrap();
+            if let Some(policy) = policy {
+                builder = builder.scheduling_policy(policy);
+            }
+            let node = builder.build(&mut sim).unwrap();
+            let order = Rc::new(RefCell::new(Vec::new()));
+            for i in 0..10 {
+                let order = order.clone();
+                node.spawn(async move { order.borrow_mut().push(i) });
+            }
+            sim.make_steps();
+            let order = order.borrow().clone();
+            order
+        };
+        let random = (0..5).map(|seed| order(seed, None)).collect::<HashSet<_>>();
+        assert!(random.len() > 1);
+        for seed in 0..5 {
+            assert_eq!(
+                order(seed, Some(SchedulingPolicy::Fifo)),
+                (0..10).collect::<Vec<_>>()
+            );
+        }
+    }
 }
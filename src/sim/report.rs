@@ -0,0 +1,42 @@
+use std::{fmt, net::IpAddr};
+
+use crate::time::Timestamp;
+
+use super::runtime::TaskInfo;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeReport {
+    pub ip: IpAddr,
+    pub tasks: Vec<TaskInfo>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Unfinished tasks of the simulation nodes,
+/// describes why the simulation got stuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StuckReport {
+    pub time: Timestamp,
+    /// Only nodes with unfinished tasks are listed.
+    pub nodes: Vec<NodeReport>,
+}
+
+impl fmt::Display for StuckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "simulation got stuck at {:?}", self.time)?;
+        for node in self.nodes.iter() {
+            writeln!(
+                f,
+                "node {} has {} unfinished task(s):",
+                node.ip,
+                node.tasks.len()
+            )?;
+            for task in node.tasks.iter() {
+                writeln!(f, "    {}", task)?;
+            }
+        }
+        Ok(())
+    }
+}
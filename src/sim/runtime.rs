@@ -1,21 +1,29 @@
 use std::{
     cell::{RefCell, RefMut},
     future::Future,
-    pin::Pin,
+    panic::{AssertUnwindSafe, Location},
     rc::Rc,
     sync::Arc,
-    task::{Context, Poll},
+    task::Context,
 };
 
 use futures::FutureExt;
-use task::Task;
-use thiserror::Error;
-
 use state::RuntimeState;
 use waker::Waker;
 
+pub use join::{AbortHandle, JoinError, JoinHandle};
+pub use panic::{PanicContext, PanicPolicy};
+pub use scheduler::SchedulingPolicy;
+pub use task::{TaskId, TaskInfo, TaskState};
+
+pub(crate) use panic::{PanicOrigin, TaskPanic};
+pub(crate) use scheduler::Scheduler;
+
 ////////////////////////////////////////////////////////////////////////////////
 
+mod join;
+mod panic;
+mod scheduler;
 mod state;
 mod task;
 mod waker;
@@ -34,29 +42,71 @@ impl Runtime {
         self.0.borrow_mut()
     }
 
+    pub fn set_scheduler(&self, scheduler: Scheduler) {
+        self.state().set_scheduler(scheduler);
+    }
+
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.state().set_panic_policy(policy);
+    }
+
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.0.borrow().panic_policy()
+    }
+
+    /// Sets the node described in the panics of the tasks.
+    pub fn set_panic_origin(&self, origin: PanicOrigin) {
+        self.state().set_panic_origin(origin);
+    }
+
+    /// Panic of the task which must be handled by the caller,
+    /// if the policy is not [`PanicPolicy::Capture`].
+    pub fn take_panic(&self) -> Option<TaskPanic> {
+        self.state().take_panic()
+    }
+
     pub fn has_work(&self) -> bool {
         self.0.borrow().queue_size() > 0
     }
 
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.0.borrow().tasks()
+    }
+
     pub fn next_step(&self) -> bool {
-        let Some(mut task) = self.state().take_task() else {
+        let Some((key, mut future)) = self.state().take_task() else {
             return false;
         };
 
         let waker = futures::task::waker(Arc::new(Waker {
             handle: Rc::downgrade(&self.0),
-            task_id: task.id(),
+            task: key,
         }));
 
         let mut context = Context::from_waker(&waker);
 
-        if task.poll(&mut context).is_pending() {
-            self.state().add_task(task);
-        }
+        let finished = future.as_mut().poll(&mut context).is_ready();
+
+        // future must be dropped outside of the state borrow
+        let future = self.state().finish_poll(key, future, finished);
+        drop(future);
 
         true
     }
 
+    /// Drops all tasks of the runtime.
+    pub fn clear(&self) {
+        // dropping a task can wake other tasks,
+        // so tasks must be dropped outside of the state borrow
+        loop {
+            let tasks = self.state().clear();
+            if tasks.is_empty() {
+                break;
+            }
+            drop(tasks);
+        }
+    }
+
     #[allow(unused)]
     pub fn make_steps(&self, steps: Option<usize>) -> usize {
         let mut cnt = 0;
@@ -75,48 +125,42 @@ impl Runtime {
         cnt
     }
 
+    #[cfg(test)]
+    #[track_caller]
     pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
     where
         F: Future + 'static,
     {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let task = async move {
-            let result = task.await;
-            // if send failed, then join handle has been dropped,
-            // which is okay behaviour
-            let _ = sender.send(result);
-        };
-        self.submit(task);
-        JoinHandle { receiver }
-    }
-
-    fn submit(&self, task: impl Future<Output = ()> + 'static) {
-        let task: Task = task.into();
-        let mut state = self.state();
-        let id = task.id();
-        state.add_task(task);
-        state.push_task(id);
+        self.spawn_at(task, None, Location::caller())
     }
-}
-
-////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Error, PartialEq)]
-#[error("the task has been dropped")]
-pub struct JoinError {}
-
-pub struct JoinHandle<T> {
-    receiver: tokio::sync::oneshot::Receiver<T>,
-}
-
-impl<T> Future for JoinHandle<T> {
-    type Output = Result<T, JoinError>;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.get_mut()
-            .receiver
-            .poll_unpin(cx)
-            .map_err(|_| JoinError {})
+    pub fn spawn_at<F>(
+        &self,
+        task: F,
+        name: Option<String>,
+        location: &'static Location<'static>,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let (mut handle, guard) = JoinHandle::new(Rc::downgrade(&self.0));
+        let state = Rc::downgrade(&self.0);
+        let task = async move {
+            match AssertUnwindSafe(task).catch_unwind().await {
+                Ok(result) => guard.complete(result),
+                Err(payload) => {
+                    let panic = state
+                        .upgrade()
+                        .and_then(|state| state.borrow_mut().on_panic(payload));
+                    if let Some(panic) = panic {
+                        guard.panicked(panic);
+                    }
+                }
+            }
+        };
+        let key = self.state().add_task(Box::pin(task), name, location);
+        handle.set_task(key);
+        handle
     }
 }
 
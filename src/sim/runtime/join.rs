@@ -0,0 +1,195 @@
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll, Waker},
+};
+
+use thiserror::Error;
+
+use super::{
+    panic::{PanicContext, TaskPanic},
+    state::RuntimeState,
+    task::TaskKey,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Error)]
+pub enum JoinError {
+    #[error("the task has been cancelled")]
+    Cancelled,
+    /// Task panicked and the node uses [`PanicPolicy::Capture`](super::PanicPolicy::Capture).
+    #[error("{1}")]
+    Panicked(Box<dyn Any + Send>, Box<PanicContext>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(..))
+    }
+
+    /// Describes the panicked task, if the task has panicked.
+    pub fn panic_context(&self) -> Option<&PanicContext> {
+        match self {
+            JoinError::Panicked(_, context) => Some(context),
+            JoinError::Cancelled => None,
+        }
+    }
+
+    /// Returns the panic payload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task has not panicked.
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        self.try_into_panic()
+            .expect("`JoinError` reason is not a panic")
+    }
+
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send>, JoinError> {
+        match self {
+            JoinError::Panicked(payload, _) => Ok(payload),
+            err => Err(err),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct JoinResult<T> {
+    value: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+}
+
+/// Stores the result of the task on completion.
+/// If the task is dropped before completion, it is considered as cancelled.
+pub(crate) struct JoinGuard<T> {
+    result: Rc<RefCell<JoinResult<T>>>,
+    finished: Rc<Cell<bool>>,
+}
+
+impl<T> JoinGuard<T> {
+    pub fn complete(self, value: T) {
+        self.finish(Ok(value));
+    }
+
+    pub fn panicked(self, panic: TaskPanic) {
+        let context = Box::new(panic.context);
+        self.finish(Err(JoinError::Panicked(panic.payload, context)));
+    }
+
+    fn finish(&self, value: Result<T, JoinError>) {
+        if self.finished.replace(true) {
+            return;
+        }
+        let waker = {
+            let mut result = self.result.borrow_mut();
+            result.value = Some(value);
+            result.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for JoinGuard<T> {
+    fn drop(&mut self) {
+        self.finish(Err(JoinError::Cancelled));
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Allows to abort the task without awaiting it.
+#[derive(Clone)]
+pub struct AbortHandle {
+    runtime: Weak<RefCell<RuntimeState>>,
+    task: TaskKey,
+    finished: Rc<Cell<bool>>,
+}
+
+impl AbortHandle {
+    /// Drops the task. Awaiting the [`JoinHandle`] of the aborted task
+    /// returns [`JoinError::Cancelled`].
+    ///
+    /// If the task aborts itself, it is dropped when the current poll ends.
+    pub fn abort(&self) {
+        if self.is_finished() {
+            return;
+        }
+        let Some(runtime) = self.runtime.upgrade() else {
+            return;
+        };
+        // task must be dropped outside of the state borrow
+        let task = runtime.borrow_mut().abort(self.task);
+        drop(task);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.get()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct JoinHandle<T> {
+    result: Rc<RefCell<JoinResult<T>>>,
+    abort_handle: AbortHandle,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(runtime: Weak<RefCell<RuntimeState>>) -> (Self, JoinGuard<T>) {
+        let result = Rc::new(RefCell::new(JoinResult {
+            value: None,
+            waker: None,
+        }));
+        let finished = Rc::new(Cell::new(false));
+        let handle = Self {
+            result: result.clone(),
+            abort_handle: AbortHandle {
+                runtime,
+                task: Default::default(),
+                finished: finished.clone(),
+            },
+        };
+        (handle, JoinGuard { result, finished })
+    }
+
+    pub(crate) fn set_task(&mut self, task: TaskKey) {
+        self.abort_handle.task = task;
+    }
+
+    pub fn abort(&self) {
+        self.abort_handle.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.abort_handle.is_finished()
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort_handle.clone()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut result = self.result.borrow_mut();
+        if let Some(value) = result.value.take() {
+            Poll::Ready(value)
+        } else {
+            result.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
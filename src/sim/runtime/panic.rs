@@ -0,0 +1,97 @@
+use std::{any::Any, fmt, net::IpAddr};
+
+use crate::{sim::time::Clock, time::Timestamp};
+
+use super::task::TaskInfo;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Defines what happens when a task of a node panics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Panic is propagated to the caller stepping the simulation,
+    /// the message describes the task, the node, the time and the seed.
+    #[default]
+    Propagate,
+    /// Panic is returned as [`JoinError::Panicked`](super::JoinError::Panicked)
+    /// from the [`JoinHandle`](super::JoinHandle) of the task.
+    Capture,
+    /// The node which task panicked is crashed, the panic can be inspected
+    /// with [`NodeHandle::last_panic`](crate::sim::node::NodeHandle::last_panic).
+    CrashNode,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Describes which task panicked, where and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicContext {
+    /// Can be missing if the task aborted itself before panicking.
+    pub task: Option<TaskInfo>,
+    /// Address of the node, unspecified for the tasks of
+    /// [`Sim::block_on`](crate::sim::Sim::block_on).
+    pub ip: IpAddr,
+    /// Global time of the simulation.
+    pub time: Timestamp,
+    pub seed: u64,
+    pub message: String,
+}
+
+impl fmt::Display for PanicContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.task {
+            Some(task) => write!(f, "{}", task)?,
+            None => write!(f, "task")?,
+        }
+        if self.ip.is_unspecified() {
+            write!(f, " panicked in block_on")?;
+        } else {
+            write!(f, " panicked on node {}", self.ip)?;
+        }
+        write!(
+            f,
+            " at {:?} (seed {}): {}",
+            self.time, self.seed, self.message
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Node which owns the runtime, used to describe the panics.
+#[derive(Clone)]
+pub(crate) struct PanicOrigin {
+    pub ip: IpAddr,
+    pub seed: u64,
+    pub clock: Clock,
+}
+
+/// Panic which must be handled by the node.
+pub(crate) struct TaskPanic {
+    pub payload: Box<dyn Any + Send>,
+    pub context: PanicContext,
+}
+
+impl TaskPanic {
+    pub fn new(
+        payload: Box<dyn Any + Send>,
+        task: Option<TaskInfo>,
+        origin: Option<&PanicOrigin>,
+    ) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "Box<dyn Any>".to_owned()
+        };
+        let context = PanicContext {
+            task,
+            ip: origin.map_or(IpAddr::from([0, 0, 0, 0]), |origin| origin.ip),
+            time: origin.map_or(Timestamp::ZERO, |origin| origin.clock.time()),
+            seed: origin.map_or(0, |origin| origin.seed),
+            message,
+        };
+        Self { payload, context }
+    }
+}
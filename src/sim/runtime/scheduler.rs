@@ -0,0 +1,131 @@
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
+
+use super::task::{TaskId, TaskKey};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Defines which of the runnable tasks of a node is polled next.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Tasks are polled in the order they were woken.
+    #[default]
+    Fifo,
+    /// Next task is chosen uniformly at random.
+    Random,
+    /// Probabilistic concurrency testing: tasks get random priorities
+    /// and the runnable task with the highest priority is polled.
+    /// Priority of the polled task is lowered at `depth - 1` random
+    /// steps chosen among the first `steps` steps.
+    Pct { depth: usize, steps: usize },
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) struct Scheduler {
+    policy: SchedulingPolicy,
+    rng: StdRng,
+    step: usize,
+    // sorted in descending order, so the next change point is the last one
+    change_points: Vec<usize>,
+    priorities: HashMap<TaskId, u64>,
+    // runnable tasks, may contain keys of the removed tasks
+    queue: VecDeque<TaskKey>,
+    // runnable tasks by priority for the PCT policy
+    heap: BinaryHeap<(u64, TaskKey)>,
+}
+
+impl Scheduler {
+    pub fn new(policy: SchedulingPolicy, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let change_points = match policy {
+            SchedulingPolicy::Pct { depth, steps } => {
+                let amount = depth.saturating_sub(1).min(steps);
+                let mut points = index::sample(&mut rng, steps, amount).into_vec();
+                points.sort_by(|a, b| b.cmp(a));
+                points
+            }
+            _ => Vec::new(),
+        };
+        Self {
+            policy,
+            rng,
+            step: 0,
+            change_points,
+            priorities: HashMap::new(),
+            queue: VecDeque::new(),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Adds the runnable task.
+    pub fn push(&mut self, key: TaskKey) {
+        match self.policy {
+            SchedulingPolicy::Pct { depth, .. } => {
+                let priority = self.priority(key.id, depth);
+                self.heap.push((priority, key));
+            }
+            _ => self.queue.push_back(key),
+        }
+    }
+
+    /// Takes the next task for which `is_live` is true,
+    /// keys of the removed tasks are skipped.
+    pub fn pop(&mut self, mut is_live: impl FnMut(TaskKey) -> bool) -> Option<TaskKey> {
+        loop {
+            let key = match self.policy {
+                SchedulingPolicy::Fifo => self.queue.pop_front(),
+                SchedulingPolicy::Random if self.queue.is_empty() => None,
+                SchedulingPolicy::Random => {
+                    let index = self.rng.gen_range(0..self.queue.len());
+                    self.queue.swap_remove_back(index)
+                }
+                SchedulingPolicy::Pct { .. } => self.heap.pop().map(|(_, key)| key),
+            }?;
+            if is_live(key) {
+                return Some(key);
+            }
+        }
+    }
+
+    /// Removes all keys from the queue.
+    pub fn take_queue(&mut self) -> Vec<TaskKey> {
+        let heap = std::mem::take(&mut self.heap).into_sorted_vec();
+        self.queue
+            .drain(..)
+            .chain(heap.into_iter().rev().map(|(_, key)| key))
+            .collect()
+    }
+
+    /// Must be called when the picked task is going to be polled.
+    pub fn on_poll(&mut self, task_id: TaskId) {
+        if self.change_points.last() == Some(&self.step) {
+            self.change_points.pop();
+            // priorities below `depth` are reserved for the change points
+            let priority = self.change_points.len() as u64 + 1;
+            self.priorities.insert(task_id, priority);
+        }
+        self.step += 1;
+    }
+
+    pub fn forget(&mut self, task_id: TaskId) {
+        self.priorities.remove(&task_id);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    fn priority(&mut self, task_id: TaskId, depth: usize) -> u64 {
+        let rng = &mut self.rng;
+        *self
+            .priorities
+            .entry(task_id)
+            .or_insert_with(|| rng.gen_range(depth as u64 + 1..u64::MAX))
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(SchedulingPolicy::Fifo, 0)
+    }
+}
@@ -1,38 +1,201 @@
-use std::collections::{HashMap, VecDeque};
+use std::{any::Any, panic::Location};
 
-use super::task::{Task, TaskId};
+use slab::Slab;
+
+use super::{
+    panic::{PanicOrigin, PanicPolicy, TaskPanic},
+    scheduler::Scheduler,
+    task::{BoxFuture, Task, TaskId, TaskInfo, TaskKey},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Default)]
 pub(crate) struct RuntimeState {
-    task_queue: VecDeque<TaskId>,
-    tasks: HashMap<TaskId, Task>,
+    tasks: Slab<Task>,
+    // number of tasks with the scheduled flag, the queue of the scheduler
+    // can also hold keys of the removed tasks
+    scheduled: usize,
+    next_id: u64,
+    // task which is being polled now
+    polled: Option<TaskKey>,
+    scheduler: Scheduler,
+    panic_policy: PanicPolicy,
+    panic_origin: Option<PanicOrigin>,
+    panic: Option<TaskPanic>,
 }
 
 impl RuntimeState {
-    pub fn take_task(&mut self) -> Option<Task> {
-        // some tasks from queue may be already resolved,
-        // (there can be duplicates in task queue)
-        while let Some(task_id) = self.task_queue.pop_front() {
-            if let Some(task) = self.tasks.remove(&task_id) {
-                return Some(task);
+    /// Takes the future of the next task to poll.
+    pub fn take_task(&mut self) -> Option<(TaskKey, BoxFuture)> {
+        let tasks = &self.tasks;
+        let key = self.scheduler.pop(|key| {
+            tasks
+                .get(key.index)
+                .is_some_and(|task| task.id == key.id && task.scheduled)
+        })?;
+        let task = &mut self.tasks[key.index];
+        task.scheduled = false;
+        self.scheduled -= 1;
+        task.polls += 1;
+        self.scheduler.on_poll(key.id);
+        self.polled = Some(key);
+        Some((key, task.future.take().unwrap()))
+    }
+
+    /// Returns the future back after the poll.
+    /// If the future must be dropped, it is returned to the caller.
+    pub fn finish_poll(
+        &mut self,
+        key: TaskKey,
+        future: BoxFuture,
+        finished: bool,
+    ) -> Option<BoxFuture> {
+        self.polled = None;
+        // the task could abort itself or the runtime could be cleared
+        // while the task was polled
+        if self.get_mut(key).is_none() {
+            return Some(future);
+        }
+        if finished {
+            self.remove(key);
+            Some(future)
+        } else {
+            self.tasks[key.index].future = Some(future);
+            None
+        }
+    }
+
+    pub fn set_scheduler(&mut self, mut scheduler: Scheduler) {
+        for key in self.scheduler.take_queue() {
+            if self.get_mut(key).is_some() {
+                scheduler.push(key);
             }
         }
+        self.scheduler = scheduler;
+    }
+
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+
+    pub fn set_panic_origin(&mut self, origin: PanicOrigin) {
+        self.panic_origin = Some(origin);
+    }
+
+    /// Called when the polled task panics.
+    /// Returns the panic back if it must be passed to the join handle.
+    pub fn on_panic(&mut self, payload: Box<dyn Any + Send>) -> Option<TaskPanic> {
+        let task = self
+            .polled
+            .and_then(|key| self.tasks.get(key.index).filter(|task| task.id == key.id))
+            .map(|task| task.info());
+        let panic = TaskPanic::new(payload, task, self.panic_origin.as_ref());
+        if self.panic_policy == PanicPolicy::Capture {
+            return Some(panic);
+        }
+        self.panic = Some(panic);
         None
     }
 
-    pub fn add_task(&mut self, task: Task) {
-        let id = task.id();
-        let prev_task = self.tasks.insert(id, task);
-        assert!(prev_task.is_none());
+    pub fn take_panic(&mut self) -> Option<TaskPanic> {
+        self.panic.take()
     }
 
-    pub fn push_task(&mut self, task_id: TaskId) {
-        self.task_queue.push_back(task_id)
+    pub fn add_task(
+        &mut self,
+        future: BoxFuture,
+        name: Option<String>,
+        location: &'static Location<'static>,
+    ) -> TaskKey {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        let index = self.tasks.insert(Task {
+            id,
+            future: Some(future),
+            name,
+            location,
+            polls: 0,
+            scheduled: true,
+        });
+        let key = TaskKey { index, id };
+        self.scheduled += 1;
+        self.scheduler.push(key);
+        key
+    }
+
+    /// Schedules the task if it is not finished and not scheduled yet.
+    pub fn wake_task(&mut self, key: TaskKey) {
+        if let Some(task) = self.get_mut(key) {
+            if !task.scheduled {
+                task.scheduled = true;
+                self.scheduled += 1;
+                self.scheduler.push(key);
+            }
+        }
     }
 
     pub fn queue_size(&self) -> usize {
-        self.task_queue.len()
+        self.scheduled
+    }
+
+    /// Tasks which are not finished yet.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let mut tasks = self
+            .tasks
+            .iter()
+            .map(|(_, task)| task.info())
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| {
+            (
+                task.location.file(),
+                task.location.line(),
+                task.location.column(),
+                task.id,
+            )
+        });
+        tasks
+    }
+
+    /// Removes the task, its future must be dropped by the caller.
+    /// If the task is being polled now, the future is dropped after the poll.
+    pub fn abort(&mut self, key: TaskKey) -> Option<BoxFuture> {
+        self.get_mut(key)?;
+        self.remove(key).future
+    }
+
+    /// Removes all tasks, their futures must be dropped by the caller.
+    pub fn clear(&mut self) -> Vec<BoxFuture> {
+        self.scheduled = 0;
+        self.scheduler.take_queue();
+        self.tasks
+            .drain()
+            .filter_map(|task| {
+                self.scheduler.forget(task.id);
+                task.future
+            })
+            .collect()
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    fn get_mut(&mut self, key: TaskKey) -> Option<&mut Task> {
+        self.tasks
+            .get_mut(key.index)
+            .filter(|task| task.id == key.id)
+    }
+
+    fn remove(&mut self, key: TaskKey) -> Task {
+        let task = self.tasks.remove(key.index);
+        // the key is skipped when it is taken from the queue
+        if task.scheduled {
+            self.scheduled -= 1;
+        }
+        self.scheduler.forget(key.id);
+        task
     }
 }
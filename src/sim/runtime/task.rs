@@ -1,33 +1,94 @@
-use std::{
-    future::Future,
-    ops::Deref,
-    pin::Pin,
-    task::{Context, Poll},
-};
+use std::{fmt, future::Future, panic::Location, pin::Pin};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub(crate) struct Task(Pin<Box<dyn Future<Output = ()>>>);
+pub(crate) type BoxFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+pub(crate) struct Task {
+    pub id: TaskId,
+    /// Taken out while the task is being polled.
+    pub future: Option<BoxFuture>,
+    pub name: Option<String>,
+    pub location: &'static Location<'static>,
+    pub polls: u64,
+    /// Whether the task is in the runtime queue.
+    pub scheduled: bool,
+}
 
 impl Task {
-    pub fn id(&self) -> TaskId {
-        self.0.deref() as *const dyn Future<Output = ()> as *const () as TaskId
+    pub fn info(&self) -> TaskInfo {
+        let state = if self.future.is_none() {
+            TaskState::Running
+        } else if self.scheduled {
+            TaskState::Runnable
+        } else {
+            TaskState::Waiting
+        };
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            location: self.location,
+            polls: self.polls,
+            state,
+        }
     }
+}
 
-    pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
-        self.0.as_mut().poll(cx)
-    }
+/// Position of the task in the runtime slab.
+/// Slots are reused, so the key also holds the id of the task.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TaskKey {
+    pub index: usize,
+    pub id: TaskId,
 }
 
-impl<T> From<T> for Task
-where
-    T: Future<Output = ()> + 'static,
-{
-    fn from(value: T) -> Self {
-        Task(Box::pin(value))
+////////////////////////////////////////////////////////////////////////////////
+
+/// Identifier of the task, never reused within the node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(pub(crate) u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub(crate) type TaskId = usize;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Task is being polled now.
+    Running,
+    /// Task is woken and waits to be polled.
+    Runnable,
+    /// Task waits to be woken.
+    Waiting,
+}
+
+/// Snapshot of the task state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    /// Name set with [`Builder::name`](crate::sim::task::Builder::name).
+    pub name: Option<String>,
+    /// Location of the `spawn` call.
+    pub location: &'static Location<'static>,
+    /// How many times the task has been polled.
+    pub polls: u64,
+    pub state: TaskState,
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " '{}'", name)?;
+        }
+        write!(
+            f,
+            " ({:?}, polled {} times) spawned at {}",
+            self.state, self.polls, self.location
+        )
+    }
+}
@@ -1,4 +1,14 @@
-use super::Runtime;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    future::poll_fn,
+    rc::Rc,
+    task::{Poll, Waker},
+};
+
+use test_case::test_case;
+
+use super::{AbortHandle, JoinError, PanicPolicy, Runtime, Scheduler, SchedulingPolicy};
 
 #[test]
 fn basic() {
@@ -56,7 +66,263 @@ fn handle_lifetime() {
     runtime.make_steps(None);
     runtime.spawn(async {
         let result = handle.await;
-        assert_eq!(result, Ok(5));
+        assert_eq!(result.unwrap(), 5);
+    });
+    runtime.make_steps(None);
+}
+
+#[test]
+fn abort() {
+    let runtime = Runtime::new();
+    let (_sender, receiver) = tokio::sync::oneshot::channel::<()>();
+    let handle = runtime.spawn(async move {
+        receiver.await.unwrap();
+    });
+    runtime.make_steps(None);
+    assert!(!handle.is_finished());
+    handle.abort();
+    assert!(handle.is_finished());
+    runtime.spawn(async {
+        let result = handle.await;
+        assert!(matches!(result, Err(JoinError::Cancelled)));
+        assert!(result.unwrap_err().is_cancelled());
+    });
+    assert_eq!(runtime.make_steps(None), 1);
+    assert!(!runtime.has_work());
+}
+
+#[test]
+fn abort_handle() {
+    let runtime = Runtime::new();
+    let handle = runtime.spawn(async { 5 });
+    let abort_handle = handle.abort_handle();
+    runtime.make_steps(None);
+    assert!(abort_handle.is_finished());
+    // finished task is not affected
+    abort_handle.abort();
+    runtime.spawn(async {
+        assert_eq!(handle.await.unwrap(), 5);
+    });
+    runtime.make_steps(None);
+
+    let handle = runtime.spawn(async { 10 });
+    handle.abort_handle().abort();
+    assert_eq!(runtime.make_steps(None), 0);
+    assert!(handle.is_finished());
+}
+
+#[test]
+fn abort_itself() {
+    let runtime = Runtime::new();
+    let (sender, receiver) = tokio::sync::oneshot::channel::<AbortHandle>();
+    let flag = Rc::new(Cell::new(false));
+    let handle = runtime.spawn({
+        let flag = flag.clone();
+        async move {
+            receiver.await.unwrap().abort();
+            futures::pending!();
+            flag.set(true);
+        }
     });
+    assert!(sender.send(handle.abort_handle()).is_ok());
     runtime.make_steps(None);
+    assert!(handle.is_finished());
+    assert!(!flag.get());
+}
+
+#[test]
+fn wakeups_deduplicated() {
+    let runtime = Runtime::new();
+    let waker = Rc::new(RefCell::new(None::<Waker>));
+    let polls = Rc::new(Cell::new(0));
+    runtime.spawn({
+        let waker = waker.clone();
+        let polls = polls.clone();
+        poll_fn(move |cx| {
+            polls.set(polls.get() + 1);
+            if polls.get() > 1 {
+                return Poll::Ready(());
+            }
+            *waker.borrow_mut() = Some(cx.waker().clone());
+            // wakes itself while being polled
+            cx.waker().wake_by_ref();
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+    });
+    assert!(runtime.next_step());
+    let waker = waker.borrow_mut().take().unwrap();
+    waker.wake_by_ref();
+    waker.wake_by_ref();
+    assert_eq!(runtime.make_steps(None), 1);
+    assert_eq!(polls.get(), 2);
+}
+
+#[test]
+fn stale_waker() {
+    let runtime = Runtime::new();
+    let waker = Rc::new(RefCell::new(None::<Waker>));
+    runtime.spawn({
+        let waker = waker.clone();
+        poll_fn(move |cx| {
+            *waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Ready(())
+        })
+    });
+    assert_eq!(runtime.make_steps(None), 1);
+
+    // new task takes the slot of the finished one
+    runtime.spawn(futures::future::pending::<()>());
+    assert_eq!(runtime.make_steps(None), 1);
+
+    waker.borrow_mut().take().unwrap().wake();
+    assert!(!runtime.has_work());
+    assert_eq!(runtime.make_steps(None), 0);
+}
+
+#[test]
+fn monotonic_ids() {
+    let runtime = Runtime::new();
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        runtime.spawn(futures::future::pending::<()>());
+        ids.push(runtime.tasks()[0].id);
+        runtime.clear();
+    }
+    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn many_tasks() {
+    let runtime = Runtime::new();
+    let counter = Rc::new(Cell::new(0));
+    for _ in 0..10_000 {
+        let counter = counter.clone();
+        runtime.spawn(async move {
+            yield_now().await;
+            counter.set(counter.get() + 1);
+        });
+    }
+    assert_eq!(runtime.make_steps(None), 20_000);
+    assert_eq!(counter.get(), 10_000);
+    assert!(runtime.tasks().is_empty());
+}
+
+#[test]
+fn panic_captured() {
+    let runtime = Runtime::new();
+    runtime.set_panic_policy(PanicPolicy::Capture);
+    let handle = runtime.spawn(async {
+        panic!("boom");
+    });
+    let result = Rc::new(RefCell::new(None));
+    runtime.spawn({
+        let result = result.clone();
+        async move {
+            *result.borrow_mut() = Some(handle.await);
+        }
+    });
+    runtime.make_steps(None);
+    assert!(runtime.take_panic().is_none());
+    let err = result.borrow_mut().take().unwrap().unwrap_err();
+    assert!(err.is_panic());
+    assert_eq!(*err.into_panic().downcast::<&str>().unwrap(), "boom");
+}
+
+#[test]
+fn panic_reported() {
+    let runtime = Runtime::new();
+    let handle = runtime.spawn_at(
+        async {
+            panic!("boom {}", 5);
+        },
+        Some("panicking".to_owned()),
+        std::panic::Location::caller(),
+    );
+    assert!(runtime.next_step());
+    let panic = runtime.take_panic().unwrap();
+    assert_eq!(panic.context.message, "boom 5");
+    assert_eq!(
+        panic.context.task.unwrap().name.as_deref(),
+        Some("panicking")
+    );
+    assert!(handle.is_finished());
+    assert!(runtime.tasks().is_empty());
+}
+
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+fn execution_order(policy: SchedulingPolicy, seed: u64) -> Vec<usize> {
+    let runtime = Runtime::new();
+    runtime.set_scheduler(Scheduler::new(policy, seed));
+    let order = Rc::new(RefCell::new(Vec::new()));
+    for i in 0..10 {
+        let order = order.clone();
+        runtime.spawn(async move {
+            yield_now().await;
+            order.borrow_mut().push(i);
+        });
+    }
+    runtime.make_steps(None);
+    let order = order.borrow().clone();
+    order
+}
+
+#[test]
+fn fifo_scheduling() {
+    assert_eq!(
+        execution_order(SchedulingPolicy::Fifo, 1),
+        (0..10).collect::<Vec<_>>()
+    );
+}
+
+#[test_case(SchedulingPolicy::Random)]
+#[test_case(SchedulingPolicy::Pct { depth: 3, steps: 20 })]
+fn randomized_scheduling(policy: SchedulingPolicy) {
+    let orders = (0..10)
+        .map(|seed| execution_order(policy, seed))
+        .collect::<HashSet<_>>();
+    assert!(orders.len() > 1);
+    for order in orders.iter() {
+        let mut order = order.clone();
+        order.sort();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+    }
+    assert_eq!(execution_order(policy, 5), execution_order(policy, 5));
+}
+
+#[test_case(SchedulingPolicy::Fifo)]
+#[test_case(SchedulingPolicy::Random)]
+#[test_case(SchedulingPolicy::Pct { depth: 3, steps: 20 })]
+fn aborted_tasks_skipped(policy: SchedulingPolicy) {
+    let runtime = Runtime::new();
+    runtime.set_scheduler(Scheduler::new(policy, 123));
+    let polled = Rc::new(RefCell::new(Vec::new()));
+    let spawn = |i: usize| {
+        let polled = polled.clone();
+        runtime.spawn(async move { polled.borrow_mut().push(i) })
+    };
+    let handles = (0..4).map(spawn).collect::<Vec<_>>();
+    handles[1].abort();
+    handles[2].abort();
+    // the slot of the aborted task is reused while its key is still queued
+    spawn(4);
+    assert!(runtime.has_work());
+    assert_eq!(runtime.make_steps(None), 3);
+    assert!(!runtime.has_work());
+    let mut polled = polled.borrow().clone();
+    polled.sort();
+    assert_eq!(polled, vec![0, 3, 4]);
 }
@@ -2,13 +2,13 @@ use std::{cell::RefCell, rc::Weak, sync::Arc};
 
 use futures::task::ArcWake;
 
-use super::{state::RuntimeState, task::TaskId};
+use super::{state::RuntimeState, task::TaskKey};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) struct Waker {
     pub handle: Weak<RefCell<RuntimeState>>,
-    pub task_id: TaskId,
+    pub task: TaskKey,
 }
 
 // Waker will not be send between threads by design
@@ -20,6 +20,6 @@ impl ArcWake for Waker {
         let Some(state) = arc_self.handle.upgrade() else {
             return;
         };
-        state.borrow_mut().push_task(arc_self.task_id);
+        state.borrow_mut().wake_task(arc_self.task);
     }
 }
@@ -4,6 +4,7 @@ use super::{node::NodeHandle, runtime::JoinHandle};
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[track_caller]
 pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
 where
     F: Future + 'static,
@@ -57,7 +58,7 @@ mod tests {
             async move {
                 spawn(async move {
                     cnt.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                })
+                });
             }
         });
 
@@ -66,7 +67,7 @@ mod tests {
             async move {
                 spawn(async move {
                     cnt.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                })
+                });
             }
         });
 
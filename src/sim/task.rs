@@ -0,0 +1,122 @@
+use std::{future::Future, panic::Location};
+
+use super::{node::NodeHandle, runtime::JoinHandle};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Configures the task before spawning it.
+#[derive(Debug, Default, Clone)]
+pub struct Builder {
+    name: Option<String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the task, shown in the task snapshots and reports.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Spawns the task on the current node.
+    #[track_caller]
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.spawn_on(future, &NodeHandle::current())
+    }
+
+    /// Spawns the task on the specified node.
+    #[track_caller]
+    pub fn spawn_on<F>(self, future: F, node: &NodeHandle) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        node.spawn_at(future, self.name, Location::caller())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::sim::{node::builder::NodeBuilder, Sim, TaskState};
+
+    use super::Builder;
+
+    #[test]
+    fn names_and_states() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("1.1.1.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        Builder::new().name("receiver").spawn_on(
+            async move {
+                let _ = receiver.await;
+            },
+            &node,
+        );
+        node.spawn(async move {
+            Builder::new()
+                .name("sender")
+                .spawn(async move {
+                    let _ = sender.send(());
+                })
+                .await
+                .unwrap();
+        });
+
+        let tasks = node.tasks();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].name.as_deref(), Some("receiver"));
+        assert_eq!(tasks[1].name, None);
+        assert!(tasks.iter().all(|t| t.state == TaskState::Runnable));
+        assert!(tasks.iter().all(|t| t.polls == 0));
+        assert!(tasks[0].location.file().ends_with("task.rs"));
+
+        // receiver waits, spawner polled and spawned sender
+        node.make_steps(Some(2));
+        let tasks = node.tasks();
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].state, TaskState::Waiting);
+        assert_eq!(tasks[0].polls, 1);
+        assert_eq!(tasks[1].state, TaskState::Waiting);
+        assert_eq!(tasks[2].name.as_deref(), Some("sender"));
+        assert_eq!(tasks[2].state, TaskState::Runnable);
+        assert_eq!(tasks.iter().map(|t| t.id).collect::<HashSet<_>>().len(), 3);
+
+        node.make_steps(None);
+        assert!(node.tasks().is_empty());
+    }
+
+    #[test]
+    fn running_task() {
+        let mut sim = Sim::new(123);
+        let node = NodeBuilder::with_ip("1.1.1.1")
+            .unwrap()
+            .build(&mut sim)
+            .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        Builder::new().name("observer").spawn_on(
+            async move {
+                let tasks = crate::sim::node::NodeHandle::current().tasks();
+                sender.send(tasks).unwrap();
+            },
+            &node,
+        );
+        node.make_steps(None);
+        let tasks = receiver.recv().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].name.as_deref(), Some("observer"));
+        assert_eq!(tasks[0].state, TaskState::Running);
+        assert_eq!(tasks[0].polls, 1);
+    }
+}
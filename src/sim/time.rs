@@ -1,14 +1,15 @@
 use std::{
-    cell::{RefCell, RefMut},
-    cmp::Ordering,
-    collections::BinaryHeap,
-    future::poll_fn,
+    cell::{Cell, OnceCell, RefCell, RefMut},
+    collections::BTreeMap,
+    future::{poll_fn, Future},
+    pin::Pin,
     rc::Rc,
-    task::Poll,
-    time::Duration,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 
 use futures::task::AtomicWaker;
+use thiserror::Error;
 
 use super::node::NodeHandle;
 use crate::time::Timestamp;
@@ -18,25 +19,88 @@ use crate::time::Timestamp;
 pub(crate) struct TimerEntry {
     pub timestamp: Timestamp,
     pub waker: AtomicWaker,
+    id: u64,
 }
 
-impl PartialEq for TimerEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.timestamp == other.timestamp
+impl TimerEntry {
+    // timers with equal timestamps are fired in the order of creation
+    fn key(&self) -> (Timestamp, u64) {
+        (self.timestamp, self.id)
     }
 }
 
-impl Eq for TimerEntry {}
+////////////////////////////////////////////////////////////////////////////////
+
+struct ClockState {
+    time: Cell<Timestamp>,
+    epoch: Cell<SystemTime>,
+}
+
+/// Global simulation clock, shared by all nodes and the network.
+#[derive(Clone)]
+pub(crate) struct Clock(Rc<ClockState>);
+
+impl Clock {
+    pub fn new() -> Self {
+        Self(Rc::new(ClockState {
+            time: Cell::new(Timestamp::ZERO),
+            epoch: Cell::new(SystemTime::UNIX_EPOCH),
+        }))
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.0.time.get()
+    }
+
+    pub fn advance_to_time(&self, to: Timestamp) {
+        assert!(self.time() <= to);
+        self.0.time.set(to);
+    }
+
+    /// System time at the start of the simulation.
+    pub fn epoch(&self) -> SystemTime {
+        self.0.epoch.get()
+    }
 
-impl Ord for TimerEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.timestamp.cmp(&self.timestamp)
+    pub fn set_epoch(&self, epoch: SystemTime) {
+        self.0.epoch.set(epoch);
     }
 }
 
-impl PartialOrd for TimerEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+////////////////////////////////////////////////////////////////////////////////
+
+/// Maps the global time to the local time of a node:
+/// `local = base_local + (global - base_global) * (1 + drift_ppm / 10^6)`.
+#[derive(Default, Clone, Copy)]
+struct LocalClock {
+    base_global: Timestamp,
+    base_local: Timestamp,
+    drift_ppm: i32,
+}
+
+impl LocalClock {
+    const PPM: i128 = 1_000_000;
+
+    fn rate(&self) -> i128 {
+        Self::PPM + self.drift_ppm as i128
+    }
+
+    fn local(&self, global: Timestamp) -> Timestamp {
+        let elapsed = global.saturating_sub(self.base_global).as_nanos() as i128;
+        let elapsed = elapsed * self.rate() / Self::PPM;
+        self.base_local + Duration::from_nanos(elapsed as u64)
+    }
+
+    fn global(&self, local: Timestamp) -> Timestamp {
+        let elapsed = local.saturating_sub(self.base_local).as_nanos() as i128;
+        // round up, so local time reaches `local` at the returned moment
+        let elapsed = (elapsed * Self::PPM + self.rate() - 1) / self.rate();
+        self.base_global + Duration::from_nanos(elapsed as u64)
+    }
+
+    fn rebase(&mut self, global: Timestamp, local: Timestamp) {
+        self.base_global = global;
+        self.base_local = local;
     }
 }
 
@@ -44,82 +108,410 @@ impl PartialOrd for TimerEntry {
 
 #[derive(Default)]
 pub struct TimeState {
-    heap: BinaryHeap<Rc<TimerEntry>>,
-    time: Timestamp,
+    timers: BTreeMap<(Timestamp, u64), Rc<TimerEntry>>,
+    next_timer_id: u64,
+    local_clock: LocalClock,
+    // nanoseconds added to the wall-clock time, not to the local time
+    system_offset: i128,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct TimeDriver(RefCell<TimeState>);
+/// Timers and local clock of a single node.
+///
+/// The local time is derived from the global [`Clock`], it can be skewed
+/// and can drift. Timer entries store the local time.
+pub struct TimeDriver {
+    state: RefCell<TimeState>,
+    clock: Clock,
+}
 
 impl TimeDriver {
-    pub fn new() -> Self {
-        Self(RefCell::new(Default::default()))
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            state: Default::default(),
+            clock,
+        }
     }
 
     pub fn add_timer(&self, timestamp: Timestamp) -> Rc<TimerEntry> {
+        let mut state = self.state();
+        let id = state.next_timer_id;
+        state.next_timer_id += 1;
         let waker = AtomicWaker::new();
-        let entry = Rc::new(TimerEntry { timestamp, waker });
-        self.state().heap.push(entry.clone());
+        let entry = Rc::new(TimerEntry {
+            timestamp,
+            waker,
+            id,
+        });
+        state.timers.insert(entry.key(), entry.clone());
         entry
     }
 
+    /// Cancels the timer if it is not fired yet.
+    pub fn remove_timer(&self, entry: &TimerEntry) {
+        self.state().timers.remove(&entry.key());
+    }
+
+    #[cfg(test)]
     pub fn next_timer(&self) -> Option<Rc<TimerEntry>> {
-        self.state().heap.peek().cloned()
+        self.peek()
+    }
+
+    /// Global time at which the next timer expires.
+    pub fn next_timer_global_time(&self) -> Option<Timestamp> {
+        let state = self.state();
+        let (&(local, _), _) = state.timers.first_key_value()?;
+        Some(state.local_clock.global(local))
     }
 
+    #[cfg(test)]
     pub fn advance_to_next_timer(&self) -> bool {
-        let next = self.state().heap.pop();
-        if let Some(next) = next {
-            self.state().time = next.timestamp;
-            next.waker.wake();
-            true
-        } else {
-            false
+        let Some(global) = self.next_timer_global_time() else {
+            return false;
+        };
+        if self.clock.time() < global {
+            self.clock.advance_to_time(global);
         }
+        let (_, next) = self.state().timers.pop_first().unwrap();
+        next.waker.wake();
+        true
     }
 
+    /// Fires all timers which expire not later than the global time `to`.
+    /// Global clock is moved forward to `to` if it is behind.
     pub fn advance_to_time(&self, to: Timestamp) {
-        assert!(self.state().time <= to);
+        if self.clock.time() < to {
+            self.clock.advance_to_time(to);
+        }
+        let now = self.time();
         while let Some(entry) = self.peek() {
-            if entry.timestamp <= to {
-                self.advance_to_next_timer();
+            if entry.timestamp <= now {
+                self.state().timers.pop_first();
+                entry.waker.wake();
             } else {
                 break;
             }
         }
-        self.state().time = to;
     }
 
+    /// Local time of the node.
     pub fn time(&self) -> Timestamp {
-        self.state().time
+        self.state().local_clock.local(self.clock.time())
+    }
+
+    /// Wall-clock time of the node.
+    pub fn system_time(&self) -> SystemTime {
+        let time = self.clock.epoch() + self.time();
+        let offset = self.state().system_offset;
+        let delta = Duration::from_nanos(offset.unsigned_abs() as u64);
+        if offset >= 0 {
+            time + delta
+        } else {
+            time - delta
+        }
+    }
+
+    /// Sets the wall-clock time, the local time is not changed.
+    pub fn set_system_time(&self, time: SystemTime) {
+        self.state().system_offset = 0;
+        let offset = match time.duration_since(self.system_time()) {
+            Ok(delta) => delta.as_nanos() as i128,
+            Err(err) => -(err.duration().as_nanos() as i128),
+        };
+        self.state().system_offset = offset;
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn jump_forward(&self, delta: Duration) {
+        let local = self.time() + delta;
+        self.state().local_clock.rebase(self.clock.time(), local);
+    }
+
+    /// Local time is saturated at zero.
+    pub fn jump_backward(&self, delta: Duration) {
+        let local = self.time().saturating_sub(delta);
+        self.state().local_clock.rebase(self.clock.time(), local);
+    }
+
+    pub fn set_drift(&self, drift_ppm: i32) {
+        assert!(
+            (drift_ppm as i128) > -LocalClock::PPM,
+            "clock drift must be greater than -10^6 ppm"
+        );
+        let local = self.time();
+        let mut state = self.state();
+        state.local_clock.rebase(self.clock.time(), local);
+        state.local_clock.drift_ppm = drift_ppm;
+    }
+
+    /// Cancels all timers.
+    pub fn clear(&self) {
+        self.state().timers.clear();
     }
 
     ////////////////////////////////////////////////////////////////////////////////
 
     fn state(&self) -> RefMut<'_, TimeState> {
-        self.0.borrow_mut()
+        self.state.borrow_mut()
     }
 
     fn peek(&self) -> Option<Rc<TimerEntry>> {
-        self.state().heap.peek().cloned()
+        self.state()
+            .timers
+            .first_key_value()
+            .map(|(_, entry)| entry.clone())
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub async fn sleep(duration: Duration) {
-    let node_handle = NodeHandle::current();
-    let timer_entry = node_handle.add_timer(duration);
-    poll_fn(move |cx| {
-        timer_entry.waker.register(cx.waker());
-        if timer_entry.timestamp <= node_handle.time() {
+/// Future returned by [`sleep`].
+///
+/// The timer is registered on creation if it happens on a node,
+/// otherwise on the first poll. It is cancelled when the future is dropped.
+pub struct Sleep {
+    delay: Delay,
+    timer: OnceCell<(NodeHandle, Rc<TimerEntry>)>,
+}
+
+/// Deadline of the timer which is not registered yet.
+#[derive(Clone, Copy)]
+enum Delay {
+    Until(Timestamp),
+    /// Relative to the registration time.
+    For(Duration),
+}
+
+impl Sleep {
+    fn new(delay: Delay) -> Self {
+        let sleep = Self {
+            delay,
+            timer: OnceCell::new(),
+        };
+        if NodeHandle::exists() {
+            sleep.timer();
+        }
+        sleep
+    }
+
+    fn timer(&self) -> &(NodeHandle, Rc<TimerEntry>) {
+        self.timer.get_or_init(|| {
+            let node = NodeHandle::get_current().expect("sleep is used outside of a node");
+            let deadline = match self.delay {
+                Delay::Until(deadline) => deadline,
+                Delay::For(duration) => node.time() + duration,
+            };
+            let entry = node.add_timer(deadline);
+            (node, entry)
+        })
+    }
+
+    fn node(&self) -> &NodeHandle {
+        &self.timer().0
+    }
+
+    /// Local time of the node at which the future completes.
+    pub fn deadline(&self) -> Timestamp {
+        self.timer().1.timestamp
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        self.deadline() <= self.node().time()
+    }
+
+    /// Resets the future to complete at the new deadline.
+    pub fn reset(&mut self, deadline: Timestamp) {
+        self.delay = Delay::Until(deadline);
+        if let Some((node, entry)) = self.timer.get_mut() {
+            node.remove_timer(entry);
+            *entry = node.add_timer(deadline);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.timer().1.waker.register(cx.waker());
+        if self.is_elapsed() {
             Poll::Ready(())
         } else {
             Poll::Pending
         }
-    })
-    .await;
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // sleep can be dropped after the simulation
+        if let Some((node, entry)) = self.timer.get() {
+            if node.alive() {
+                node.remove_timer(entry);
+            }
+        }
+    }
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::new(Delay::For(duration))
+}
+
+/// Waits until the local time of the node reaches `deadline`.
+pub fn sleep_until(deadline: Timestamp) -> Sleep {
+    Sleep::new(Delay::Until(deadline))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("deadline has elapsed")]
+pub struct Elapsed(());
+
+/// Future returned by [`timeout`].
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    delay: Sleep,
+}
+
+impl<F> Timeout<F> {
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    pub fn into_inner(self) -> F
+    where
+        F: Unpin,
+    {
+        *Pin::into_inner(self.future)
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        // the inner future is polled first, so it can complete
+        // even if the deadline has already elapsed
+        if let Poll::Ready(output) = this.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.delay).poll(cx).map(|_| Err(Elapsed(())))
+    }
+}
+
+/// Requires `future` to complete within `duration`.
+/// The deadline is set when the function is called on a node,
+/// otherwise when the future is first polled.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        delay: sleep(duration),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Defines what [`Interval`] does when ticks are missed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Missed ticks fire as fast as possible.
+    #[default]
+    Burst,
+    /// Ticks are rescheduled `period` after the moment the missed tick fired.
+    Delay,
+    /// Missed ticks are skipped, next tick is aligned with the original schedule.
+    Skip,
+}
+
+impl MissedTickBehavior {
+    fn next_timeout(&self, timeout: Timestamp, now: Timestamp, period: Duration) -> Timestamp {
+        match self {
+            Self::Burst => timeout + period,
+            Self::Delay => now + period,
+            Self::Skip => {
+                let behind = (now - timeout).as_nanos() % period.as_nanos();
+                now + period - Duration::from_nanos(behind as u64)
+            }
+        }
+    }
+}
+
+/// Stream of ticks returned by [`interval`] and [`interval_at`].
+pub struct Interval {
+    delay: Sleep,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl Interval {
+    /// Completes when the next tick is reached.
+    /// Returns the scheduled time of the tick.
+    pub async fn tick(&mut self) -> Timestamp {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Timestamp> {
+        if Pin::new(&mut self.delay).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let timeout = self.delay.deadline();
+        let now = self.delay.node().time();
+        let next = if now > timeout {
+            self.missed_tick_behavior
+                .next_timeout(timeout, now, self.period)
+        } else {
+            timeout + self.period
+        };
+        self.delay.reset(next);
+        Poll::Ready(timeout)
+    }
+
+    /// Next tick will happen `period` after the current time.
+    pub fn reset(&mut self) {
+        let now = self.delay.node().time();
+        self.delay.reset(now + self.period);
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+/// Creates an [`Interval`] with the first tick completing immediately.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(NodeHandle::current().time(), period)
+}
+
+/// Creates an [`Interval`] with the first tick completing at `start`.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval_at(start: Timestamp, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "`period` must be non-zero");
+    Interval {
+        delay: sleep_until(start),
+        period,
+        missed_tick_behavior: Default::default(),
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -128,6 +520,14 @@ pub fn now() -> Duration {
     NodeHandle::current().time()
 }
 
+/// Wall-clock time of the current node, which starts
+/// from the epoch set with [`Sim::set_epoch`](super::Sim::set_epoch).
+/// It follows the local clock of the node and can also be set separately
+/// with [`NodeHandle::set_system_time`], which does not affect [`now`].
+pub fn system_time() -> SystemTime {
+    NodeHandle::current().system_time()
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -138,6 +538,7 @@ mod tests {
     };
 
     use futures::task::{waker, ArcWake};
+    use test_case::test_case;
 
     use crate::sim::{node::NodeBuilder, spawn, Sim};
 
@@ -158,7 +559,7 @@ mod tests {
 
     #[test]
     fn driver_works() {
-        let driver = TimeDriver::new();
+        let driver = TimeDriver::new(Clock::new());
         assert_eq!(driver.time(), Duration::from_secs(0));
 
         driver.add_timer(Duration::from_secs(1));
@@ -182,7 +583,7 @@ mod tests {
 
     #[test]
     fn driver_wakes_up() {
-        let driver = TimeDriver::new();
+        let driver = TimeDriver::new(Clock::new());
         let entry = driver.add_timer(Duration::from_secs(1));
         struct Waker {
             wakeups: AtomicUsize,
@@ -311,6 +712,289 @@ mod tests {
             }
         });
         node.step_duration(Duration::from_secs(0));
-        assert_eq!(flag.load(std::sync::atomic::Ordering::SeqCst), true);
+        assert!(flag.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn driver_removes_timer() {
+        let driver = TimeDriver::new(Clock::new());
+        let first = driver.add_timer(Duration::from_secs(1));
+        let second = driver.add_timer(Duration::from_secs(1));
+        driver.remove_timer(&first);
+        assert_eq!(driver.next_timer().unwrap().id, second.id);
+        driver.remove_timer(&second);
+        assert!(driver.next_timer().is_none());
+        driver.remove_timer(&second);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    #[test]
+    fn dropped_sleep_cancelled() {
+        let (sim, node) = make_node();
+        node.spawn(async {
+            for _ in 0..1000 {
+                let sleep = sleep(Duration::from_secs(1));
+                let ready = futures::future::ready(());
+                futures::future::select(sleep, ready).await;
+            }
+        });
+        sim.make_steps();
+        assert_eq!(sim.time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn sleep_reset() {
+        let (sim, node) = make_node();
+        node.spawn(async {
+            let mut sleep = sleep(Duration::from_secs(10));
+            assert_eq!(sleep.deadline(), Duration::from_secs(10));
+            sleep.reset(Duration::from_secs(3));
+            (&mut sleep).await;
+            assert_eq!(now(), Duration::from_secs(3));
+            assert!(sleep.is_elapsed());
+            sleep.reset(Duration::from_secs(5));
+            assert!(!sleep.is_elapsed());
+            sleep.await;
+            assert_eq!(now(), Duration::from_secs(5));
+        });
+        sim.make_steps();
+        assert_eq!(sim.time(), Duration::from_secs(5));
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    #[test]
+    fn local_clock_drift() {
+        let driver = TimeDriver::new(Clock::new());
+        driver.set_drift(100_000);
+        driver.jump_forward(Duration::from_secs(10));
+        let entry = driver.add_timer(Duration::from_secs(21));
+        assert_eq!(
+            driver.next_timer_global_time(),
+            Some(Duration::from_secs(10))
+        );
+        driver.advance_to_time(Duration::from_millis(9999));
+        assert_eq!(driver.next_timer().unwrap().timestamp, entry.timestamp);
+        driver.advance_to_time(Duration::from_secs(10));
+        assert_eq!(driver.time(), Duration::from_secs(21));
+        assert!(driver.next_timer().is_none());
+
+        driver.set_drift(-500_000);
+        driver.add_timer(Duration::from_secs(22));
+        assert_eq!(
+            driver.next_timer_global_time(),
+            Some(Duration::from_secs(12))
+        );
+    }
+
+    #[test]
+    fn skewed_nodes() {
+        let mut sim = Sim::new(123);
+        let ahead = NodeBuilder::with_ip("1.1.1.1")
+            .unwrap()
+            .clock_offset(Duration::from_secs(100))
+            .build(&mut sim)
+            .unwrap();
+        let fast = NodeBuilder::with_ip("1.1.1.2")
+            .unwrap()
+            .clock_drift(250_000)
+            .build(&mut sim)
+            .unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for node in [&ahead, &fast] {
+            node.spawn({
+                let log = log.clone();
+                let ip = node.ip();
+                async move {
+                    let start = now();
+                    sleep(Duration::from_secs(5)).await;
+                    assert_eq!(now() - start, Duration::from_secs(5));
+                    log.borrow_mut().push((ip, now()));
+                }
+            });
+        }
+        sim.make_steps();
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                (fast.ip(), Duration::from_secs(5)),
+                (ahead.ip(), Duration::from_secs(105))
+            ]
+        );
+        assert_eq!(sim.time(), Duration::from_secs(5));
+        assert_eq!(fast.time(), Duration::from_millis(6250));
+    }
+
+    #[test]
+    fn jump_clock() {
+        let (sim, node) = make_node();
+        let woken_at = Rc::new(RefCell::new(Vec::new()));
+        for _ in 0..2 {
+            node.spawn({
+                let woken_at = woken_at.clone();
+                async move {
+                    sleep(Duration::from_secs(5)).await;
+                    woken_at.borrow_mut().push(now());
+                }
+            });
+        }
+        sim.run_for(Duration::from_secs(1));
+        node.jump_clock(Duration::from_secs(3));
+        assert_eq!(node.time(), Duration::from_secs(4));
+        sim.make_steps();
+        assert_eq!(sim.time(), Duration::from_secs(2));
+        assert_eq!(woken_at.borrow().len(), 2);
+
+        node.spawn({
+            let woken_at = woken_at.clone();
+            async move {
+                sleep(Duration::from_secs(1)).await;
+                woken_at.borrow_mut().push(now());
+            }
+        });
+        node.jump_clock_back(Duration::from_secs(10));
+        assert_eq!(node.time(), Duration::ZERO);
+        sim.make_steps();
+        assert_eq!(woken_at.borrow().last(), Some(&Duration::from_secs(1)));
+        assert_eq!(sim.time(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn system_time_works() {
+        let mut sim = Sim::new(123);
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        sim.set_epoch(epoch);
+        let node = NodeBuilder::with_ip("1.1.1.1")
+            .unwrap()
+            .clock_offset(Duration::from_secs(3))
+            .build(&mut sim)
+            .unwrap();
+        node.spawn(async move {
+            assert_eq!(system_time(), epoch + Duration::from_secs(3));
+            sleep(Duration::from_secs(2)).await;
+            assert_eq!(system_time(), epoch + Duration::from_secs(5));
+            assert_eq!(now(), Duration::from_secs(5));
+        });
+        sim.make_steps();
+        assert_eq!(node.system_time(), epoch + Duration::from_secs(5));
+        node.jump_clock_back(Duration::from_secs(1));
+        assert_eq!(node.system_time(), epoch + Duration::from_secs(4));
+
+        // wall-clock time is stepped without the local time
+        node.set_system_time(epoch - Duration::from_secs(60));
+        assert_eq!(node.time(), Duration::from_secs(4));
+        node.spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            assert_eq!(system_time(), epoch - Duration::from_secs(59));
+            assert_eq!(now(), Duration::from_secs(5));
+        });
+        sim.make_steps();
+        node.set_system_time(epoch + Duration::from_secs(100));
+        assert_eq!(node.system_time(), epoch + Duration::from_secs(100));
+        assert_eq!(node.time(), Duration::from_secs(5));
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+
+    #[test]
+    fn sleep_until_works() {
+        let (sim, node) = make_node();
+        node.spawn(async {
+            sleep_until(Duration::from_secs(3)).await;
+            assert_eq!(now(), Duration::from_secs(3));
+            // deadline in the past completes immediately
+            sleep_until(Duration::from_secs(1)).await;
+            assert_eq!(now(), Duration::from_secs(3));
+        });
+        sim.make_steps();
+        assert_eq!(sim.time(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn timeout_works() {
+        let (sim, node) = make_node();
+        node.spawn(async {
+            let result = timeout(Duration::from_secs(2), sleep(Duration::from_secs(1))).await;
+            assert_eq!(result, Ok(()));
+            assert_eq!(now(), Duration::from_secs(1));
+
+            let result = timeout(Duration::from_secs(2), sleep(Duration::from_secs(5))).await;
+            assert_eq!(result, Err(Elapsed(())));
+            assert_eq!(now(), Duration::from_secs(3));
+
+            let result = timeout(Duration::ZERO, async { 5 }).await;
+            assert_eq!(result, Ok(5));
+
+            for _ in 0..1000 {
+                let _ = timeout(Duration::from_secs(100), async {}).await;
+            }
+        });
+        sim.make_steps();
+        // the inner sleep and the timers of completed timeouts are cancelled
+        assert_eq!(sim.time(), Duration::from_secs(3));
+    }
+
+    #[test_case(MissedTickBehavior::Burst, &[0, 1000, 2000, 4500, 4500, 5000, 6000])]
+    #[test_case(MissedTickBehavior::Delay, &[0, 1000, 2000, 4500, 5500, 6500, 7500])]
+    #[test_case(MissedTickBehavior::Skip, &[0, 1000, 2000, 4500, 5000, 6000, 7000])]
+    fn interval_works(behavior: MissedTickBehavior, expected: &'static [u64]) {
+        let (sim, node) = make_node();
+        let ticks = Rc::new(RefCell::new(Vec::new()));
+        node.spawn({
+            let ticks = ticks.clone();
+            async move {
+                let mut interval = interval(Duration::from_secs(1));
+                interval.set_missed_tick_behavior(behavior);
+                for i in 0..expected.len() {
+                    interval.tick().await;
+                    ticks.borrow_mut().push(now().as_millis() as u64);
+                    if i == 2 {
+                        sleep(Duration::from_millis(2500)).await;
+                    }
+                }
+            }
+        });
+        sim.make_steps();
+        assert_eq!(*ticks.borrow(), expected);
+    }
+
+    #[test]
+    fn interval_at_works() {
+        let (sim, node) = make_node();
+        node.spawn(async {
+            let mut interval = interval_at(Duration::from_secs(5), Duration::from_secs(2));
+            assert_eq!(interval.tick().await, Duration::from_secs(5));
+            assert_eq!(interval.tick().await, Duration::from_secs(7));
+            sleep(Duration::from_millis(500)).await;
+            interval.reset();
+            assert_eq!(interval.tick().await, Duration::from_millis(9500));
+            assert_eq!(now(), Duration::from_millis(9500));
+        });
+        sim.make_steps();
+    }
+
+    #[test]
+    fn sleep_created_outside_node() {
+        let (sim, node) = make_node();
+        sim.run_for(Duration::from_secs(1));
+        let sleep = node.spawn(sleep(Duration::from_secs(2)));
+        let timeout = node.spawn(timeout(
+            Duration::from_secs(1),
+            std::future::pending::<()>(),
+        ));
+        sim.make_steps();
+        assert!(sleep.is_finished());
+        assert!(timeout.is_finished());
+        // deadlines are set on the first poll
+        assert_eq!(sim.time(), Duration::from_secs(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "sleep is used outside of a node")]
+    fn sleep_polled_outside_node() {
+        let mut sleep = Box::pin(sleep(Duration::from_secs(1)));
+        let waker = futures::task::noop_waker();
+        let _ = sleep.as_mut().poll(&mut Context::from_waker(&waker));
     }
 }